@@ -3,10 +3,13 @@ use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 use crc::{Crc, CRC_32_ISO_HDLC};
 use log::debug;
+use crate::rom;
 
 pub const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
+pub mod everdrive;
 pub mod sixtyfourdrive;
+pub mod summercart64;
 
 lazy_static! {
     pub static ref ROMDB: HashMap<String, SaveType> = {
@@ -18,9 +21,9 @@ lazy_static! {
         
         let mut entries = HashMap::new();
         
-        let mut lines = include_str!("romdb.ini").lines();
+        let lines = include_str!("romdb.ini").lines();
         let mut entry = None;
-        while let Some(line) = lines.next() {
+        for line in lines {
             if line.starts_with("[") {
                 entry = Some(DbEntry::default());
                 
@@ -121,11 +124,11 @@ impl Cic {
     /// Attempts to detect which CIC variant matches the provided ROM.
     /// 
     /// If ROM does not include standard 0x40 byte header, or is smaller than 0x1000 bytes, this method
-    /// will fail.
+    /// will fail. Byte-swapped and little-endian ROMs are normalized first.
     pub fn from_rom(data: &[u8]) -> Cic {
         if data.len() < 0x1000 { return Cic::Unknown }
         
-        Self::from_ipl3(&data[0x40..0x1000])
+        Self::from_ipl3(&rom::normalize(&data[0..0x1000])[0x40..])
     }
     
     /// Attempts to detect which CIC variant matches the provided IPL3.
@@ -149,9 +152,10 @@ impl Cic {
 }
 
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
 pub enum SaveType {
     Auto,
+    #[default]
     Nothing,
     Eeprom4Kbit,
     Eeprom16Kbit,
@@ -161,11 +165,6 @@ pub enum SaveType {
     FlashRam1MbitStadium,
     Unknown,
 }
-impl Default for SaveType {
-    fn default() -> Self {
-        SaveType::Nothing
-    }
-}
 impl FromStr for SaveType {
     type Err = String;
 
@@ -187,8 +186,24 @@ impl FromStr for SaveType {
     }
 }
 impl SaveType {
+    /// Size of the save data in bytes, or `None` if this savetype doesn't have any.
+    pub fn length(self) -> Option<u32> {
+        use SaveType::*;
+        
+        match self {
+            Eeprom4Kbit => Some(512),
+            Eeprom16Kbit => Some(2 * 1024),
+            Sram256Kbit => Some(32 * 1024),
+            Sram768Kbit => Some(96 * 1024),
+            FlashRam1Mbit | FlashRam1MbitStadium => Some(128 * 1024),
+            Auto | Nothing | Unknown => None,
+        }
+    }
+    
+    /// Looks up the ROM's savetype in the ROM database, by the md5 hash of its big-endian image.
+    /// Byte-swapped and little-endian ROMs are normalized first.
     pub fn from_rom(data: &[u8]) -> SaveType {
-        let hash = md5::compute(data).0;
+        let hash = md5::compute(rom::normalize(data)).0;
         let mut hash_str = String::new();
         for byte in hash {
             hash_str.push_str(&format!("{:02X}", byte));
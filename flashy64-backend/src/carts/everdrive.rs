@@ -0,0 +1,342 @@
+use std::cmp::min;
+use std::time::Duration;
+use bytes::{BufMut, BytesMut};
+use log::debug;
+use crate::{Capabilities, CartInfo, Flashcart, Result};
+use crate::carts::{Cic, SaveType};
+use crate::Error::{CommunicationFailed, Unsupported};
+use crate::progress::{Progress, Tracker};
+use crate::transport::Transport;
+use crate::unfloader;
+use crate::unfloader::{DataType, DebugResponse, ProtocolVersion, Session};
+
+/// Cartridge address where the ROM image begins.
+pub const ROM_ADDRESS: u32 = 0x10000000;
+/// Cartridge address of the SRAM and FlashRAM save data.
+pub const SAVE_ADDRESS: u32 = 0x08000000;
+/// Maximum size of a ROM image.
+pub const ROM_MAX_LENGTH: usize = 64 * 1024 * 1024;
+/// ROMs smaller than this are zero-filled up to this size first, so that the IPL3 checksum area
+/// doesn't contain leftover data from a previous upload.
+pub const ROM_FILL_LENGTH: u32 = 0x200000;
+
+/// All transfers to and from the cartridge's memory are done in blocks of this size.
+const BLOCK_SIZE: usize = 512;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Command {
+    Test,
+    RomFill {
+        addr: u32,
+        length: u32,
+    },
+    RomWrite {
+        addr: u32,
+        length: u32,
+    },
+    RomRead {
+        addr: u32,
+        length: u32,
+    },
+    Start,
+}
+impl Command {
+    pub fn id(&self) -> u8 {
+        use Command::*;
+        match self {
+            Test => b't',
+            RomFill { .. } => b'c',
+            RomWrite { .. } => b'W',
+            RomRead { .. } => b'R',
+            Start => b's',
+        }
+    }
+    
+    /// Encodes the 16 byte command packet. Lengths are sent as a count of 512 byte blocks.
+    pub fn encode_packet(&self) -> Vec<u8> {
+        let mut packet = BytesMut::from([0x63, 0x6D, 0x64, self.id()].as_ref());
+        
+        use Command::*;
+        let (addr, length) = match self {
+            Test | Start => (0, 0),
+            RomFill { addr, length } | RomWrite { addr, length } | RomRead { addr, length } => (*addr, *length),
+        };
+        
+        packet.put_u32(addr);
+        packet.put_u32(length / BLOCK_SIZE as u32);
+        packet.put_u32(0);
+        
+        packet.to_vec()
+    }
+}
+
+#[derive(Debug)]
+pub struct EverDrive<T: Transport> {
+    device: T,
+    session: Session,
+}
+impl<T: Transport> Flashcart for EverDrive<T> {
+    fn upload_rom(&mut self, data: &[u8], progress: Progress) -> Result<()> {
+        if data.len() > ROM_MAX_LENGTH {
+            return Err(CommunicationFailed(format!("EverDrive: ROM is too large: {} bytes (max {ROM_MAX_LENGTH})", data.len())));
+        }
+        
+        self.session.rom_uploaded();
+        if (data.len() as u32) < ROM_FILL_LENGTH {
+            debug!("Filling ROM space with zeros.");
+            self.send_command(Command::RomFill { addr: ROM_ADDRESS, length: ROM_FILL_LENGTH })?;
+            self.test()?;
+        }
+        
+        self.upload(ROM_ADDRESS, data, progress)
+    }
+    
+    fn download_rom(&mut self, length: u32, progress: Progress) -> Result<Vec<u8>> {
+        self.download(ROM_ADDRESS, length, progress)
+    }
+    
+    fn upload_rom_at(&mut self, offset: u32, data: &[u8], progress: Progress) -> Result<()> {
+        self.session.rom_uploaded();
+        self.upload(ROM_ADDRESS + offset, data, progress)
+    }
+    
+    fn download_rom_at(&mut self, offset: u32, length: u32, progress: Progress) -> Result<Vec<u8>> {
+        self.download(ROM_ADDRESS + offset, length, progress)
+    }
+    
+    fn upload_save(&mut self, savetype: SaveType, data: &[u8], progress: Progress) -> Result<()> {
+        let length = save_length(savetype).ok_or(Unsupported)?;
+        if data.len() > length as usize {
+            return Err(CommunicationFailed(format!("EverDrive: save data is too large: {} bytes (max {length})", data.len())));
+        }
+        
+        self.upload(SAVE_ADDRESS, data, progress)
+    }
+    
+    fn download_save(&mut self, savetype: SaveType, progress: Progress) -> Result<Vec<u8>> {
+        let length = save_length(savetype).ok_or(Unsupported)?;
+        
+        self.download(SAVE_ADDRESS, length, progress)
+    }
+    
+    fn set_cic(&mut self, _cic: Cic) -> Result<()> {
+        // The EverDrive OS detects the CIC from the ROM's IPL3 when the game is started.
+        Err(Unsupported)
+    }
+    
+    /// The EverDrive OS reads the savetype from the "advanced homebrew header", located at the end
+    /// of the ROM header. This method patches that header in the already uploaded ROM.
+    fn set_savetype(&mut self, savetype: SaveType) -> Result<()> {
+        let savetype_index = savetype_index(savetype).ok_or(Unsupported)?;
+        
+        let mut header = self.download(ROM_ADDRESS, BLOCK_SIZE as u32, None)?;
+        header[0x3C] = b'E';
+        header[0x3D] = b'D';
+        header[0x3F] = savetype_index << 4;
+        self.upload(ROM_ADDRESS, &header, None)?;
+        
+        debug!("SaveType is set {savetype_index:#04X}");
+        Ok(())
+    }
+    
+    fn recv_debug(&mut self) -> Result<DebugResponse> {
+        let buf = self.device_read(4)?;
+        if buf != b"DMA@" {
+            debug!("buf mismatch: {buf:02X?}");
+            std::thread::sleep(Duration::from_millis(5));
+            self.device.purge()?;
+            return Err(CommunicationFailed(format!("EverDrive: debug packet mismatch: {buf:02X?} vs expected {:02X?}", b"DMA@")))
+        }
+        
+        let [kind, length @ ..]: [u8; 4] = self.device_read(4)?.try_into().unwrap();
+        
+        let kind = DataType::from(kind);
+        let length = u32::from_be_bytes([0, length[0], length[1], length[2]]) as usize;
+        
+        // "CMPH" directly follows the data, and the whole frame is padded to a multiple of 16 bytes.
+        let mut data = self.device_read(debug_frame_length(length) - 8)?;
+        let complete = data.split_off(length);
+        if &complete[0..4] != b"CMPH" {
+            return Err(CommunicationFailed(format!("EverDrive: complete packet mismatch: {:02X?} vs expected {:02X?}", &complete[0..4], b"CMPH")));
+        }
+        
+        debug!("Received {kind:?} data: {data:02X?}");
+        self.session.receive(kind, &data);
+        
+        Ok((kind, data))
+    }
+    
+    fn poll_debug(&mut self) -> Result<Option<DebugResponse>> {
+        if self.device.bytes_available()? == 0 {
+            return Ok(None);
+        }
+        
+        self.recv_debug().map(Some)
+    }
+    
+    fn send_debug(&mut self, kind: DataType, data: &[u8]) -> Result<()> {
+        let mut frame = BytesMut::from(b"DMA@".as_ref());
+        frame.put_u32(unfloader::encode_header(kind, data.len()));
+        frame.put_slice(data);
+        frame.put_slice(b"CMPH");
+        
+        let mut frame = frame.to_vec();
+        frame.resize(align(frame.len(), BLOCK_SIZE), 0x00);
+        
+        self.device_write(frame)?;
+        
+        debug!("Sent {kind:?} data: {data:02X?}");
+        Ok(())
+    }
+    
+    fn protocol_version(&self) -> ProtocolVersion {
+        self.session.protocol()
+    }
+    
+    /// The EverDrive OS doesn't report its version over USB, so only the USB identity is known.
+    fn info(&mut self) -> Result<CartInfo> {
+        Ok(CartInfo {
+            model: "EverDrive 64".into(),
+            firmware: None,
+            variant: None,
+            serial_number: self.device.identity()?.serial_number,
+            capabilities: Capabilities {
+                set_cic: false,
+                set_savetype: true,
+                eeprom_saves: false,
+                max_rom_length: ROM_MAX_LENGTH,
+            },
+        })
+    }
+    
+    fn boot(&mut self) -> Result<()> {
+        // The start command is followed by the name of the ROM's save file, which isn't needed here.
+        self.send_command(Command::Start)?;
+        self.device_write([0u8; 256])?;
+        
+        debug!("Game started.");
+        Ok(())
+    }
+}
+impl<T: Transport> EverDrive<T> {
+    pub fn new(mut device: T) -> Result<Self> {
+        device.set_timeouts(Duration::from_secs(10), Duration::from_secs(10))?;
+        device.purge()?;
+        
+        let mut cart = Self {
+            device,
+            session: Session::new(),
+        };
+        
+        cart.test()?;
+        
+        Ok(cart)
+    }
+    
+    /// Checks that the EverDrive OS is listening for commands.
+    fn test(&mut self) -> Result<()> {
+        self.send_command(Command::Test)?;
+        
+        let response = self.device_read(16)?;
+        if &response[0..4] != b"cmdr" {
+            return Err(CommunicationFailed(format!("EverDrive: test response mismatch: {:02X?} vs expected {:02X?}", &response[0..4], b"cmdr")));
+        }
+        
+        Ok(())
+    }
+    
+    pub fn upload(&mut self, addr: u32, data: &[u8], progress: Progress) -> Result<()> {
+        const SIZE: usize = 0x800000;
+        
+        let mut tracker = Tracker::new(progress, data.len());
+        for (i, chunk) in data.chunks(SIZE).enumerate() {
+            let addr = addr + (i * SIZE) as u32;
+            let mut chunk = chunk.to_vec();
+            chunk.resize(align(chunk.len(), BLOCK_SIZE), 0x00);
+            
+            debug!("Uploading data. address: {addr:#010X}, length: {:#010X}", chunk.len());
+            self.send_command(Command::RomWrite { addr, length: chunk.len() as u32 })?;
+            self.device_write(&chunk)?;
+            tracker.advance(chunk.len());
+        }
+        
+        debug!("Upload complete!");
+        Ok(())
+    }
+    
+    pub fn download(&mut self, addr: u32, length: u32, progress: Progress) -> Result<Vec<u8>> {
+        const SIZE: u32 = 0x20000;
+        
+        let length = min(length as usize, ROM_MAX_LENGTH) as u32;
+        let mut tracker = Tracker::new(progress, length as usize);
+        let mut data = Vec::with_capacity(length as usize);
+        while (data.len() as u32) < length {
+            let offset = data.len() as u32;
+            let chunk_len = min(align((length - offset) as usize, BLOCK_SIZE) as u32, SIZE);
+            
+            debug!("Downloading data. address: {:#010X}, length: {chunk_len:#010X}", addr + offset);
+            self.send_command(Command::RomRead { addr: addr + offset, length: chunk_len })?;
+            data.extend_from_slice(&self.device_read(chunk_len as usize)?);
+            tracker.advance(chunk_len as usize);
+        }
+        data.truncate(length as usize);
+        
+        debug!("Download complete! {:.4} MiB", data.len() as f32 / (1024.0 * 1024.0));
+        Ok(data)
+    }
+    
+    fn send_command(&mut self, cmd: Command) -> Result<()> {
+        self.device_write(cmd.encode_packet())
+    }
+    
+    fn device_read(&mut self, length: usize) -> Result<Vec<u8>> {
+        let mut buf = vec![0xFFu8; length];
+        if length == 0 {
+            return Ok(buf);
+        }
+        
+        self.device.read_exact(&mut buf)?;
+        
+        Ok(buf)
+    }
+    
+    fn device_write<D: AsRef<[u8]>>(&mut self, data: D) -> Result<()> {
+        self.device.write_all(data.as_ref())
+    }
+}
+
+
+
+fn align(length: usize, alignment: usize) -> usize {
+    length.div_ceil(alignment) * alignment
+}
+
+/// Total length of a debug frame carrying `length` bytes of data: 8 header bytes, the data, and the
+/// 4 byte "CMPH" marker, padded to a multiple of 16 bytes.
+fn debug_frame_length(length: usize) -> usize {
+    align(length + 12, 16)
+}
+
+/// Gets the length of the save data for the given savetype. EEPROM is emulated inside the
+/// EverDrive's FPGA, and can't be accessed over USB.
+fn save_length(savetype: SaveType) -> Option<u32> {
+    match savetype {
+        SaveType::Eeprom4Kbit | SaveType::Eeprom16Kbit => None,
+        _ => savetype.length(),
+    }
+}
+
+fn savetype_index(savetype: SaveType) -> Option<u8> {
+    use SaveType::*;
+    
+    match savetype {
+        Nothing => Some(0),
+        Eeprom4Kbit => Some(1),
+        Eeprom16Kbit => Some(2),
+        Sram256Kbit => Some(3),
+        Sram768Kbit => Some(4),
+        FlashRam1Mbit => Some(5),
+        FlashRam1MbitStadium => Some(5),
+        Auto | Unknown => None,
+    }
+}
@@ -1,12 +1,27 @@
 use std::cmp::min;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use bytes::{BufMut, BytesMut};
-use libftd2xx::{BitMode, DeviceInfo, Ftdi, FtdiCommon};
-use log::debug;
-use crate::{Error, Flashcart, Result};
+use log::{debug, warn};
+use num_enum::{FromPrimitive, IntoPrimitive};
+use crate::{Capabilities, CartInfo, Error, Flashcart, Result};
 use crate::carts::{Cic, SaveType};
+use crate::carts::sixtyfourdrive::rpk::KNOWN_FIRMWARE;
 use crate::Error::CommunicationFailed;
-use crate::unfloader::{DataType, DebugResponse};
+use crate::progress::{Progress, Tracker};
+use crate::transport::Transport;
+use crate::unfloader;
+use crate::unfloader::{DataType, DebugResponse, ProtocolVersion, Session};
+
+pub mod rpk;
+
+/// Largest amount of data which can be sent to the N64 in a single debug packet.
+pub const DEBUG_MAX_LENGTH: usize = 8 * 1024 * 1024;
+/// ROMs larger than this don't fit in the normal cartridge address space, and need extended addressing.
+pub const EXTENDED_THRESHOLD: usize = 64 * 1024 * 1024;
+/// Time between [`Command::UpgradeReport`] requests during a firmware upgrade.
+pub const UPGRADE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Firmware upgrades which don't finish within this time are considered failed.
+pub const UPGRADE_TIMEOUT: Duration = Duration::from_secs(300);
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum Command {
@@ -24,6 +39,8 @@ pub enum Command {
     SetCicType(Cic),
     SetCiExtended(u32),
     VersionRequest,
+    UpgradeStart,
+    UpgradeReport,
 }
 impl Command {
     pub fn id(&self) -> u8 {
@@ -36,6 +53,8 @@ impl Command {
             SetCicType(_) => 0x72,
             SetCiExtended(_) => 0x74,
             VersionRequest => 0x80,
+            UpgradeStart => 0x84,
+            UpgradeReport => 0x85,
         }
     }
     
@@ -57,7 +76,7 @@ impl Command {
             SetSaveType(savetype) => packet.put_u32((savetype_index(*savetype).unwrap_or(0) as u32) & 0x0000000F),
             SetCicType(cic) => packet.put_u32((cic_index(*cic).unwrap_or(1) & 0x7) as u32 | 0x80000000),
             SetCiExtended(enable) => packet.put_u32(*enable),
-            VersionRequest => (),
+            VersionRequest | UpgradeStart | UpgradeReport => (),
         }
         
         packet.to_vec()
@@ -73,6 +92,8 @@ impl Command {
             SetCicType(_) => 0,
             SetCiExtended(_) => 0,
             VersionRequest => 8,
+            UpgradeStart => 0,
+            UpgradeReport => 4,
         }
     }
     
@@ -104,10 +125,26 @@ pub enum Segment {
     Eeprom16,
 }
 impl Segment {
-    pub fn max_length(self, cart: &mut SixtyFourDrive) -> u32 {
+    /// Gets the segment which holds save data for the given savetype.
+    /// 
+    /// `SaveType::Nothing`, `SaveType::Auto`, and `SaveType::Unknown` will return `None`.
+    pub fn from_savetype(savetype: SaveType) -> Option<Segment> {
+        use SaveType::*;
+        
+        match savetype {
+            Eeprom4Kbit => Some(Segment::Eeprom4),
+            Eeprom16Kbit => Some(Segment::Eeprom16),
+            Sram256Kbit => Some(Segment::Sram256),
+            Sram768Kbit => Some(Segment::Sram768),
+            FlashRam1Mbit | FlashRam1MbitStadium => Some(Segment::FlashRam),
+            Auto | Nothing | Unknown => None,
+        }
+    }
+    
+    pub fn max_length<T: Transport>(self, cart: &SixtyFourDrive<T>) -> u32 {
         use Segment::*;
         match self {
-            Rom if !cart.is_hw1().unwrap_or(false) => 240 * 1024 * 1024,
+            Rom if !cart.is_hw1() => 240 * 1024 * 1024,
             Rom => 64 * 1024 * 1024,
             Sram256 => 32 * 1024,
             Sram768 => 96 * 1024,
@@ -118,22 +155,112 @@ impl Segment {
     }
 }
 
+/// State of a firmware upgrade, reported by [`Command::UpgradeReport`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
+#[repr(u8)]
+pub enum UpgradeStatus {
+    Ready = 0,
+    Verifying = 1,
+    Erasing0 = 2,
+    Erasing25 = 3,
+    Erasing50 = 4,
+    Erasing75 = 5,
+    Writing0 = 6,
+    Writing25 = 7,
+    Writing50 = 8,
+    Writing75 = 9,
+    Success = 10,
+    ErrorVerify = 11,
+    ErrorErase = 12,
+    ErrorWrite = 13,
+    
+    #[num_enum(default)]
+    Unknown,
+}
+impl UpgradeStatus {
+    pub fn is_error(&self) -> bool {
+        matches!(self, UpgradeStatus::ErrorVerify | UpgradeStatus::ErrorErase | UpgradeStatus::ErrorWrite | UpgradeStatus::Unknown)
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Model {
     HW1, HW2,
 }
+impl Model {
+    /// Gets the model from the variant word of a [`Command::VersionRequest`] response, which
+    /// contains 'A' for HW1 or 'B' for HW2.
+    pub fn from_variant(variant: u32) -> Option<Model> {
+        match variant.to_be_bytes()[2] {
+            b'A' => Some(Model::HW1),
+            b'B' => Some(Model::HW2),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug)]
-pub struct SixtyFourDrive {
-    device: Ftdi,
+pub struct SixtyFourDrive<T: Transport> {
+    device: T,
+    model: Model,
+    /// Savetype last configured with [`Flashcart::set_savetype`]. Pokemon Stadium 2's FlashRAM uses
+    /// its own bank on HW1.
+    savetype: SaveType,
+    /// Variant reported by the cartridge, or `None` if its version couldn't be read.
+    variant: Option<u32>,
+    /// Firmware revision, in hundredths (e.g. 205 is version 2.05), or `None` if it couldn't be read.
+    firmware: Option<u32>,
+    /// Whether extended addressing was enabled with [`Flashcart::set_extended`]. It's off at power on.
+    extended: bool,
+    session: Session,
 }
-impl Flashcart for SixtyFourDrive {
-    fn upload_rom(&mut self, data: &[u8]) -> Result<()> {
-        self.upload(Segment::Rom, 0, data)
+impl<T: Transport> Flashcart for SixtyFourDrive<T> {
+    /// Extended addressing is enabled for ROMs larger than [`EXTENDED_THRESHOLD`], and disabled for
+    /// any others, so it always matches the uploaded ROM. It's only available on HW2, and the command
+    /// is only sent when the mode needs to change.
+    fn upload_rom(&mut self, data: &[u8], progress: Progress) -> Result<()> {
+        let max_length = Segment::Rom.max_length(self) as usize;
+        if data.len() > max_length {
+            let reason = if self.is_hw1() { ". ROMs larger than 64 MiB need extended addressing, which requires HW2" } else { "" };
+            return Err(CommunicationFailed(format!("64drive: ROM is too large: {} bytes (max {max_length}){reason}", data.len())));
+        }
+        
+        let extended = data.len() > EXTENDED_THRESHOLD;
+        if !self.is_hw1() && extended != self.extended {
+            self.set_extended(extended)?;
+        }
+        
+        self.session.rom_uploaded();
+        self.upload(Segment::Rom, 0, data, progress)
     }
 
-    fn download_rom(&mut self, length: u32) -> Result<Vec<u8>> {
-        self.download(Segment::Rom, 0, length)
+    fn download_rom(&mut self, length: u32, progress: Progress) -> Result<Vec<u8>> {
+        self.download(Segment::Rom, 0, length, progress)
+    }
+    
+    fn upload_rom_at(&mut self, offset: u32, data: &[u8], progress: Progress) -> Result<()> {
+        self.session.rom_uploaded();
+        self.upload(Segment::Rom, offset, data, progress)
+    }
+    
+    fn download_rom_at(&mut self, offset: u32, length: u32, progress: Progress) -> Result<Vec<u8>> {
+        self.download(Segment::Rom, offset, length, progress)
+    }
+    
+    fn upload_save(&mut self, savetype: SaveType, data: &[u8], progress: Progress) -> Result<()> {
+        let segment = Segment::from_savetype(savetype).ok_or(Error::Unsupported)?;
+        if data.len() > segment.max_length(self) as usize {
+            return Err(CommunicationFailed(format!("64drive: save data is too large: {} bytes (max {})", data.len(), segment.max_length(self))));
+        }
+        
+        self.upload_as(segment, savetype, 0, data, progress)
+    }
+    
+    fn download_save(&mut self, savetype: SaveType, progress: Progress) -> Result<Vec<u8>> {
+        let segment = Segment::from_savetype(savetype).ok_or(Error::Unsupported)?;
+        let length = segment.max_length(self);
+        
+        self.download_as(segment, savetype, 0, length, progress)
     }
 
     fn set_cic(&mut self, cic: Cic) -> Result<()> {
@@ -149,80 +276,227 @@ impl Flashcart for SixtyFourDrive {
         let savetype_index = (savetype_index(savetype).unwrap_or(0) as u32) & 0x0000000F;
         
         self.send_packet(Command::SetSaveType(savetype))?;
+        self.savetype = savetype;
         
         debug!("SaveType is set {:#010X}", savetype_index);
         Ok(())
     }
 
     fn recv_debug(&mut self) -> Result<DebugResponse> {
-        let buf = self.ftdi_read(4)?;
+        let buf = self.device_read(4)?;
         if buf != b"DMA@"{
             debug!("buf mismatch: {buf:02X?}");
             std::thread::sleep(Duration::from_millis(5));
-            self.device.purge_rx()?;
+            self.device.purge()?;
             return Err(Error::CommunicationFailed(format!("64drive: debug packet mismatch: {buf:02X?} vs expected {:02X?}", b"DMA@")))
         }
         
-        let [kind, length @ ..]: [u8; 4] = self.ftdi_read(4)?.try_into().unwrap();
+        let [kind, length @ ..]: [u8; 4] = self.device_read(4)?.try_into().unwrap();
         
         let kind = DataType::from(kind);
         let length = u32::from_be_bytes([0, length[0], length[1], length[2]]) as usize;
         
-        let data = self.ftdi_read(length)?;
+        let data = self.device_read(length)?;
+        self.device_read(self.session.protocol().data_padding(length))?;
         
-        let complete = self.ftdi_read(4)?;
+        let complete = self.device_read(4)?;
         if complete != b"CMPH" {
             return Err(Error::CommunicationFailed(format!("64drive: complete packet mismatch: {complete:02X?} vs expected {:02X?}", b"CMPH")));
         }
         
         debug!("Received {kind:?} data: {data:02X?}");
+        self.session.receive(kind, &data);
         
         Ok((kind, data))
     }
 
-    fn send_debug(&mut self) -> Result<()> {
-        todo!()
+    fn poll_debug(&mut self) -> Result<Option<DebugResponse>> {
+        if self.device.bytes_available()? == 0 {
+            return Ok(None);
+        }
+        
+        self.recv_debug().map(Some)
+    }
+    
+    /// Sends data to the N64 through the 64drive's target side FIFO.
+    /// 
+    /// The data is padded to a 4 byte boundary, or to a 512 byte boundary if it's longer than 512 bytes.
+    /// Like UNFLoader, the packet header contains the padded length, so the cartridge reads exactly as
+    /// many bytes as were sent.
+    fn send_debug(&mut self, kind: DataType, data: &[u8]) -> Result<()> {
+        if data.len() > DEBUG_MAX_LENGTH {
+            return Err(CommunicationFailed(format!("64drive: debug data is too large: {} bytes (max {DEBUG_MAX_LENGTH})", data.len())));
+        }
+        
+        let padded_len = if data.len() > 512 {
+            data.len().div_ceil(512) * 512
+        } else {
+            data.len().div_ceil(4) * 4
+        };
+        
+        let mut payload = BytesMut::with_capacity(padded_len + 4);
+        payload.put_u32(unfloader::encode_header(kind, padded_len));
+        payload.put_slice(data);
+        payload.resize(padded_len + 4, 0x00);
+        
+        self.send_packet(Command::TargetSideFifo(payload.to_vec()))?;
+        
+        debug!("Sent {kind:?} data: {data:02X?}");
+        Ok(())
+    }
+    
+    fn protocol_version(&self) -> ProtocolVersion {
+        self.session.protocol()
     }
 
-    fn info(&mut self) -> Result<DeviceInfo> {
-        self.device.device_info().map_err(|err| err.into())
+    fn info(&mut self) -> Result<CartInfo> {
+        Ok(CartInfo {
+            model: format!("64drive {:?}", self.model),
+            firmware: self.firmware.map(|firmware| format!("{}.{:02}", firmware / 100, firmware % 100)),
+            variant: self.variant,
+            serial_number: self.device.identity()?.serial_number,
+            capabilities: Capabilities {
+                // HW1 ignores the CIC type, and always uses the one installed on the cartridge.
+                set_cic: self.model != Model::HW1,
+                set_savetype: true,
+                eeprom_saves: true,
+                max_rom_length: Segment::Rom.max_length(self) as usize,
+            },
+        })
+    }
+    
+    fn set_extended(&mut self, enabled: bool) -> Result<()> {
+        if self.is_hw1() {
+            return Err(Error::Unsupported);
+        }
+        
+        self.send_packet(Command::SetCiExtended(enabled as u32))?;
+        self.extended = enabled;
+        
+        debug!("Extended addressing is {}", if enabled { "enabled" } else { "disabled" });
+        Ok(())
+    }
+    
+    /// Upgrades the firmware from an .rpk file, which must be a known official release (see
+    /// [`rpk`]). The file is written to SDRAM, and then flashed by the cartridge itself. The 64drive
+    /// must be power cycled afterwards.
+    fn update_firmware(&mut self, firmware: &[u8], progress: Progress) -> Result<()> {
+        let release = rpk::identify(firmware, KNOWN_FIRMWARE).map_err(|err| Error::InvalidFirmware(format!("64drive: {err}")))?;
+        if release.model != self.model {
+            return Err(Error::InvalidFirmware(format!("64drive: firmware is for {:?}, but this cartridge is {:?}", release.model, self.model)));
+        }
+        debug!("Upgrading firmware from revision {:?} to {}", self.firmware, release.revision);
+        
+        self.upload(Segment::Rom, 0, firmware, progress)?;
+        self.send_packet(Command::UpgradeStart)?;
+        
+        let start = Instant::now();
+        let mut last_status = UpgradeStatus::Ready;
+        loop {
+            let status = self.upgrade_status()?;
+            if status != last_status {
+                debug!("Upgrade status: {status:?}");
+                last_status = status;
+            }
+            
+            match status {
+                UpgradeStatus::Success => break,
+                _ if status.is_error() => return Err(CommunicationFailed(format!("64drive: firmware upgrade failed: {status:?}"))),
+                _ if start.elapsed() > UPGRADE_TIMEOUT => return Err(Error::Timeout),
+                _ => std::thread::sleep(UPGRADE_POLL_INTERVAL),
+            }
+        }
+        
+        debug!("Firmware upgrade complete!");
+        Ok(())
     }
 }
-impl SixtyFourDrive {
-    pub fn new(mut device: Ftdi) -> Result<Self> {
-        device.reset().unwrap_or_default();
+impl<T: Transport> SixtyFourDrive<T> {
+    pub fn new(mut device: T) -> Result<Self> {
         device.set_timeouts(Duration::from_secs(10), Duration::from_secs(10))?;
         
-        device.set_bit_mode(0xFF, BitMode::Reset)?;
-        device.set_bit_mode(0xFF, BitMode::SyncFifo)?;
+        device.set_sync_fifo()?;
         
-        device.purge_all()?;
+        device.purge()?;
         
-        Ok(Self {
+        let mut cart = Self {
             device,
-        })
+            model: Model::HW2,
+            savetype: SaveType::Nothing,
+            variant: None,
+            firmware: None,
+            extended: false,
+            session: Session::new(),
+        };
+        
+        // Old or damaged firmware may not answer, but the cartridge must still open so it can be updated.
+        match cart.version() {
+            Ok((variant, firmware)) => (cart.variant, cart.firmware) = (Some(variant), Some(firmware)),
+            Err(err) => {
+                warn!("Unable to read the 64drive's version: {err:?}");
+                cart.device.purge()?;
+            }
+        }
+        cart.model = match cart.variant.and_then(Model::from_variant) {
+            Some(model) => model,
+            None => {
+                // Fall back to the USB identity, since HW1 and HW2 use different FTDI chips.
+                let identity = cart.device.identity()?;
+                debug!("Unknown 64drive variant {:#010X?}, guessing model from {identity:?}", cart.variant);
+                
+                match (identity.vendor_id, identity.product_id, identity.description.as_str()) {
+                    (0x0403, 0x6010, "64drive USB device A") => Model::HW1,
+                    _ => Model::HW2,
+                }
+            }
+        };
+        debug!("64drive model: {:?}, firmware: {:?}", cart.model, cart.firmware);
+        
+        Ok(cart)
     }
     
-    fn is_hw1(&mut self) -> Result<bool> {
-        let info = self.info()?;
+    pub fn model(&self) -> Model {
+        self.model
+    }
+    
+    /// Gets the state of a firmware upgrade which was started with [`Command::UpgradeStart`].
+    pub fn upgrade_status(&mut self) -> Result<UpgradeStatus> {
+        let data = self.send_packet(Command::UpgradeReport)?;
         
-        Ok(match (info.vendor_id, info.product_id, info.description.as_str()) {
-            (0x0403, 0x6010, "64drive USB device A") => true,
-            _ => false
-        })
+        Ok(UpgradeStatus::from(data[3] & 0x0F))
     }
     
-    pub fn upload(&mut self, segment: Segment, offset: u32, data: &[u8]) -> Result<()> {
+    /// Requests the hardware variant and firmware revision.
+    pub fn version(&mut self) -> Result<(u32, u32)> {
+        let data = self.send_packet(Command::VersionRequest)?;
+        
+        Ok((
+            u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        ))
+    }
+    
+    fn is_hw1(&self) -> bool {
+        self.model == Model::HW1
+    }
+    
+    /// Uploads data to a segment. The FlashRAM bank is chosen based on the configured savetype.
+    pub fn upload(&mut self, segment: Segment, offset: u32, data: &[u8], progress: Progress) -> Result<()> {
+        self.upload_as(segment, self.savetype, offset, data, progress)
+    }
+    
+    fn upload_as(&mut self, segment: Segment, savetype: SaveType, offset: u32, data: &[u8], progress: Progress) -> Result<()> {
         const SIZE: u32 = 0x800000;
         
-        let chunks = (data.len() as f32 / SIZE as f32).ceil() as u32;
-        let bank = bank_index(&segment, self.is_hw1()?, false); //TODO detect stadium 2
+        let chunks = data.len().div_ceil(SIZE as usize) as u32;
+        let bank = bank_index(&segment, self.is_hw1(), savetype == SaveType::FlashRam1MbitStadium);
+        let mut tracker = Tracker::new(progress, data.len());
         
         let mut data_index = 0;
         for i in 0..chunks {
             let length = min(data.len() - data_index, SIZE as usize);
             
-            let addr = offset + (i as u32 * SIZE);
+            let addr = offset + (i * SIZE);
             let bank_id_len = bank | (length as u32 & 0x00FFFFFF);
             
             let cmd = Command::LoadFromPc {
@@ -236,13 +510,19 @@ impl SixtyFourDrive {
             debug!("Uploading data. offset: {addr:#010X}, banklen: {bank_id_len:#010X}");
             self.send_packet(cmd)?;
             debug!("Write complete.");
+            tracker.advance(length);
         }
         
         debug!("Upload complete!");
         Ok(())
     }
     
-    pub fn download(&mut self, segment: Segment, offset: u32, mut length: u32) -> Result<Vec<u8>> {
+    /// Downloads data from a segment. The FlashRAM bank is chosen based on the configured savetype.
+    pub fn download(&mut self, segment: Segment, offset: u32, length: u32, progress: Progress) -> Result<Vec<u8>> {
+        self.download_as(segment, self.savetype, offset, length, progress)
+    }
+    
+    fn download_as(&mut self, segment: Segment, savetype: SaveType, offset: u32, mut length: u32, progress: Progress) -> Result<Vec<u8>> {
         const SIZE: u32 = 0x20000;
         
         if length == 0 {
@@ -254,8 +534,9 @@ impl SixtyFourDrive {
         }
         length = min(length, segment.max_length(self));
         
-        let chunks = (length as f32 / SIZE as f32).ceil() as u32;
-        let bank = bank_index(&segment, self.is_hw1()?, false); //TODO detect stadium 2
+        let chunks = length.div_ceil(SIZE);
+        let bank = bank_index(&segment, self.is_hw1(), savetype == SaveType::FlashRam1MbitStadium);
+        let mut tracker = Tracker::new(progress, length as usize);
         
         let mut data = vec![];
         let mut data_index = 0;
@@ -277,6 +558,7 @@ impl SixtyFourDrive {
             debug!("Downloading data. offset: {addr:#010X}, banklen: {bank_id_len:#010X}");
             let buf = self.send_packet(cmd)?;
             debug!("Read complete.");
+            tracker.advance(buf.len());
             
             data.extend_from_slice(&buf);
         }
@@ -286,27 +568,27 @@ impl SixtyFourDrive {
     }
     
     fn send_packet(&mut self, cmd: Command) -> Result<Vec<u8>> {
-        self.ftdi_write(cmd.encode_packet())?;
+        self.device_write(cmd.encode_packet())?;
         
-        let response = self.ftdi_read(cmd.recv_length() as usize)?;
-        cmd.complete_check(self.ftdi_read(4)?)?;
+        let response = self.device_read(cmd.recv_length() as usize)?;
+        cmd.complete_check(self.device_read(4)?)?;
         
         Ok(response)
     }
     
-    fn ftdi_read(&mut self, length: usize) -> Result<Vec<u8>> {
+    fn device_read(&mut self, length: usize) -> Result<Vec<u8>> {
         let mut buf = vec![0xFFu8; length];
         if length == 0 {
             return Ok(buf);
         }
         
-        self.device.read_all(&mut buf)?;
+        self.device.read_exact(&mut buf)?;
         
         Ok(buf)
     }
     
-    fn ftdi_write<D: AsRef<[u8]>>(&mut self, data: D) -> Result<()> {
-        self.device.write_all(data.as_ref()).map_err(|err| err.into())
+    fn device_write<D: AsRef<[u8]>>(&mut self, data: D) -> Result<()> {
+        self.device.write_all(data.as_ref())
     }
 }
 
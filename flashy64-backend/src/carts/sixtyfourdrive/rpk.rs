@@ -0,0 +1,36 @@
+//! Checks for 64drive firmware upgrade packages (.rpk).
+//! 
+//! The layout of .rpk files isn't publicly documented, so they aren't parsed. A file is only
+//! accepted if its length and CRC-32 (ISO-HDLC) match an official release listed in
+//! [`KNOWN_FIRMWARE`], and it's then written to SDRAM unchanged, for the cartridge to verify and
+//! flash itself. A misread image can leave the cartridge unbootable, so any other file is refused.
+//! 
+//! Entries must come from release files published at <https://64drive.retroactive.be/>, and be
+//! added along with a test using that file, once the upgrade has been checked on real hardware.
+
+use crate::carts::CRC;
+use crate::carts::sixtyfourdrive::Model;
+
+/// An official firmware release.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KnownFirmware {
+    pub model: Model,
+    /// Firmware revision, in hundredths (e.g. 205 is version 2.05).
+    pub revision: u32,
+    /// Length of the .rpk file.
+    pub length: usize,
+    /// CRC-32 (ISO-HDLC) of the whole .rpk file.
+    pub crc: u32,
+}
+
+/// Firmware releases which may be flashed. Empty until a release has been verified.
+pub const KNOWN_FIRMWARE: &[KnownFirmware] = &[];
+
+/// Finds the release which the .rpk file matches exactly.
+pub fn identify<'a>(data: &[u8], known: &'a [KnownFirmware]) -> Result<&'a KnownFirmware, String> {
+    let crc = CRC.checksum(data);
+    
+    known.iter()
+        .find(|firmware| firmware.length == data.len() && firmware.crc == crc)
+        .ok_or_else(|| format!("file doesn't match any known firmware release ({} bytes, CRC-32 {crc:#010X}). Only verified official releases can be flashed.", data.len()))
+}
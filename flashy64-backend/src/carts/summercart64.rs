@@ -0,0 +1,493 @@
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use bytes::{BufMut, BytesMut};
+use log::debug;
+use crate::{Capabilities, CartInfo, Flashcart, Result};
+use crate::carts::{Cic, SaveType};
+use crate::Error::{CommunicationFailed, Unsupported};
+use crate::progress::{Progress, Tracker};
+use crate::transport::Transport;
+use crate::unfloader::{DataType, DebugResponse, ProtocolVersion, Session};
+
+/// Identifier returned by the SC64 in response to [`Command::IdentifierGet`].
+pub const IDENTIFIER: &[u8; 4] = b"SCv2";
+
+/// SDRAM address where the ROM image begins.
+pub const ROM_ADDRESS: u32 = 0x00000000;
+/// SDRAM address where SRAM and FlashRAM save data is stored.
+pub const SAVE_ADDRESS: u32 = 0x03FE0000;
+/// Address of the EEPROM save data.
+pub const EEPROM_ADDRESS: u32 = 0x05002000;
+/// Maximum size of a ROM image.
+pub const ROM_MAX_LENGTH: usize = 64 * 1024 * 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Config {
+    BootloaderSwitch,
+    RomWriteEnable,
+    RomShadowEnable,
+    DdMode,
+    IsvAddress,
+    BootMode,
+    SaveType,
+    CicSeed,
+    TvType,
+}
+impl Config {
+    pub fn id(self) -> u32 {
+        use Config::*;
+        match self {
+            BootloaderSwitch => 0,
+            RomWriteEnable => 1,
+            RomShadowEnable => 2,
+            DdMode => 3,
+            IsvAddress => 4,
+            BootMode => 5,
+            SaveType => 6,
+            CicSeed => 7,
+            TvType => 8,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Command {
+    IdentifierGet,
+    VersionGet,
+    StateReset,
+    ConfigGet(Config),
+    ConfigSet(Config, u32),
+    MemoryRead {
+        addr: u32,
+        length: u32,
+    },
+    MemoryWrite {
+        addr: u32,
+        data: Vec<u8>,
+    },
+    UsbWrite {
+        kind: DataType,
+        data: Vec<u8>,
+    },
+}
+impl Command {
+    pub fn id(&self) -> u8 {
+        use Command::*;
+        match self {
+            IdentifierGet => b'v',
+            VersionGet => b'V',
+            StateReset => b'R',
+            ConfigGet(_) => b'c',
+            ConfigSet(_, _) => b'C',
+            MemoryRead { .. } => b'm',
+            MemoryWrite { .. } => b'M',
+            UsbWrite { .. } => b'U',
+        }
+    }
+    
+    pub fn encode_packet(&self) -> Vec<u8> {
+        let mut packet = BytesMut::from([0x43, 0x4D, 0x44, self.id()].as_ref());
+        
+        use Command::*;
+        let (arg0, arg1, data): (u32, u32, &[u8]) = match self {
+            IdentifierGet | VersionGet | StateReset => (0, 0, &[]),
+            ConfigGet(config) => (config.id(), 0, &[]),
+            ConfigSet(config, value) => (config.id(), *value, &[]),
+            MemoryRead { addr, length } => (*addr, *length, &[]),
+            MemoryWrite { addr, data } => (*addr, data.len() as u32, data),
+            UsbWrite { kind, data } => (u8::from(*kind) as u32, data.len() as u32, data),
+        };
+        
+        packet.put_u32(arg0);
+        packet.put_u32(arg1);
+        packet.put_slice(data);
+        
+        packet.to_vec()
+    }
+}
+
+/// Packets sent by the SC64 to the host.
+#[derive(Clone, PartialEq, Debug)]
+enum Response {
+    /// Reply to a previously sent command. `error` is set if the SC64 replied with `ERR` instead of `CMP`.
+    Complete {
+        id: u8,
+        error: bool,
+        data: Vec<u8>,
+    },
+    /// Asynchronous packet, which may arrive at any time (e.g. debug data sent by the N64).
+    Packet {
+        id: u8,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug)]
+pub struct SummerCart64<T: Transport> {
+    device: T,
+    packets: VecDeque<(u8, Vec<u8>)>,
+    session: Session,
+}
+impl<T: Transport> Flashcart for SummerCart64<T> {
+    fn upload_rom(&mut self, data: &[u8], progress: Progress) -> Result<()> {
+        if data.len() > ROM_MAX_LENGTH {
+            return Err(CommunicationFailed(format!("SC64: ROM is too large: {} bytes (max {ROM_MAX_LENGTH})", data.len())));
+        }
+        
+        self.session.rom_uploaded();
+        self.upload(ROM_ADDRESS, data, progress)
+    }
+    
+    fn download_rom(&mut self, length: u32, progress: Progress) -> Result<Vec<u8>> {
+        self.download(ROM_ADDRESS, length, progress)
+    }
+    
+    fn upload_rom_at(&mut self, offset: u32, data: &[u8], progress: Progress) -> Result<()> {
+        self.session.rom_uploaded();
+        self.upload(ROM_ADDRESS + offset, data, progress)
+    }
+    
+    fn download_rom_at(&mut self, offset: u32, length: u32, progress: Progress) -> Result<Vec<u8>> {
+        self.download(ROM_ADDRESS + offset, length, progress)
+    }
+    
+    fn upload_save(&mut self, savetype: SaveType, data: &[u8], progress: Progress) -> Result<()> {
+        let (addr, length) = save_location(savetype).ok_or(Unsupported)?;
+        if data.len() > length as usize {
+            return Err(CommunicationFailed(format!("SC64: save data is too large: {} bytes (max {length})", data.len())));
+        }
+        
+        self.upload(addr, data, progress)
+    }
+    
+    fn download_save(&mut self, savetype: SaveType, progress: Progress) -> Result<Vec<u8>> {
+        let (addr, length) = save_location(savetype).ok_or(Unsupported)?;
+        
+        self.download(addr, length, progress)
+    }
+    
+    fn set_cic(&mut self, cic: Cic) -> Result<()> {
+        let seed = cic_seed(cic).ok_or(Unsupported)?;
+        
+        self.send_command(Command::ConfigSet(Config::CicSeed, seed))?;
+        
+        debug!("CIC seed is set {seed:#06X}");
+        Ok(())
+    }
+    
+    fn set_savetype(&mut self, savetype: SaveType) -> Result<()> {
+        let savetype_index = savetype_index(savetype).ok_or(Unsupported)?;
+        
+        self.send_command(Command::ConfigSet(Config::SaveType, savetype_index))?;
+        
+        debug!("SaveType is set {savetype_index:#010X}");
+        Ok(())
+    }
+    
+    fn recv_debug(&mut self) -> Result<DebugResponse> {
+        loop {
+            if let Some(response) = self.take_debug_packet() {
+                return response;
+            }
+            
+            self.recv_packet()?;
+        }
+    }
+    
+    fn poll_debug(&mut self) -> Result<Option<DebugResponse>> {
+        while self.device.bytes_available()? > 0 {
+            self.recv_packet()?;
+        }
+        
+        self.take_debug_packet().transpose()
+    }
+    
+    fn send_debug(&mut self, kind: DataType, data: &[u8]) -> Result<()> {
+        self.send_command(Command::UsbWrite {
+            kind,
+            data: data.to_vec(),
+        })?;
+        
+        debug!("Sent {kind:?} data: {data:02X?}");
+        Ok(())
+    }
+    
+    fn protocol_version(&self) -> ProtocolVersion {
+        self.session.protocol()
+    }
+    
+    fn info(&mut self) -> Result<CartInfo> {
+        let (major, minor, revision) = self.version()?;
+        
+        Ok(CartInfo {
+            model: "SummerCart64".into(),
+            firmware: Some(format!("{major}.{minor}.{revision}")),
+            variant: None,
+            serial_number: self.device.identity()?.serial_number,
+            capabilities: Capabilities {
+                set_cic: true,
+                set_savetype: true,
+                eeprom_saves: true,
+                max_rom_length: ROM_MAX_LENGTH,
+            },
+        })
+    }
+}
+impl<T: Transport> SummerCart64<T> {
+    pub fn new(mut device: T) -> Result<Self> {
+        device.set_timeouts(Duration::from_secs(10), Duration::from_secs(10))?;
+        device.purge()?;
+        
+        let mut cart = Self {
+            device,
+            packets: VecDeque::new(),
+            session: Session::new(),
+        };
+        
+        cart.reset_link()?;
+        
+        let identifier = cart.send_command(Command::IdentifierGet)?;
+        if identifier != IDENTIFIER {
+            return Err(CommunicationFailed(format!("SC64: identifier mismatch: {identifier:02X?} vs expected {IDENTIFIER:02X?}")));
+        }
+        
+        cart.send_command(Command::StateReset)?;
+        
+        Ok(cart)
+    }
+    
+    /// Resets the SC64's USB interface by toggling DTR and waiting for DSR to follow.
+    /// 
+    /// Skipped if the transport doesn't support the modem control lines.
+    fn reset_link(&mut self) -> Result<()> {
+        match self.device.set_dtr(true) {
+            Err(Unsupported) => return Ok(()),
+            result => result?,
+        }
+        self.wait_for_dsr(true)?;
+        
+        self.device.purge()?;
+        
+        self.device.set_dtr(false)?;
+        self.wait_for_dsr(false)
+    }
+    
+    fn wait_for_dsr(&mut self, state: bool) -> Result<()> {
+        const TIMEOUT: Duration = Duration::from_secs(1);
+        
+        let start = Instant::now();
+        while self.device.dsr()? != state {
+            if start.elapsed() > TIMEOUT {
+                return Err(CommunicationFailed("SC64: timed out waiting for link reset".into()));
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        
+        Ok(())
+    }
+    
+    /// Returns the firmware version as `(major, minor, revision)`.
+    pub fn version(&mut self) -> Result<(u16, u16, u32)> {
+        let data = self.send_command(Command::VersionGet)?;
+        if data.len() < 8 {
+            return Err(CommunicationFailed(format!("SC64: version response too short: {data:02X?}")));
+        }
+        
+        Ok((
+            u16::from_be_bytes([data[0], data[1]]),
+            u16::from_be_bytes([data[2], data[3]]),
+            u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        ))
+    }
+    
+    pub fn upload(&mut self, addr: u32, data: &[u8], progress: Progress) -> Result<()> {
+        const SIZE: usize = 0x100000;
+        
+        let mut tracker = Tracker::new(progress, data.len());
+        for (i, chunk) in data.chunks(SIZE).enumerate() {
+            let addr = addr + (i * SIZE) as u32;
+            
+            debug!("Uploading data. address: {addr:#010X}, length: {:#010X}", chunk.len());
+            self.send_command(Command::MemoryWrite {
+                addr,
+                data: chunk.to_vec(),
+            })?;
+            tracker.advance(chunk.len());
+        }
+        
+        debug!("Upload complete!");
+        Ok(())
+    }
+    
+    pub fn download(&mut self, addr: u32, length: u32, progress: Progress) -> Result<Vec<u8>> {
+        const SIZE: u32 = 0x100000;
+        
+        let mut tracker = Tracker::new(progress, length as usize);
+        let mut data = Vec::with_capacity(length as usize);
+        while (data.len() as u32) < length {
+            let offset = data.len() as u32;
+            let chunk_len = min(length - offset, SIZE);
+            
+            debug!("Downloading data. address: {:#010X}, length: {chunk_len:#010X}", addr + offset);
+            let buf = self.send_command(Command::MemoryRead {
+                addr: addr + offset,
+                length: chunk_len,
+            })?;
+            
+            if buf.len() as u32 != chunk_len {
+                return Err(CommunicationFailed(format!("SC64: expected {chunk_len} bytes, received {}", buf.len())));
+            }
+            data.extend_from_slice(&buf);
+            tracker.advance(buf.len());
+        }
+        
+        debug!("Download complete! {:.4} MiB", data.len() as f32 / (1024.0 * 1024.0));
+        Ok(data)
+    }
+    
+    /// Sends a command and waits for its response. Any asynchronous packets received in the meantime
+    /// are queued, to be handled later (e.g. by [`Flashcart::recv_debug`]).
+    fn send_command(&mut self, cmd: Command) -> Result<Vec<u8>> {
+        self.device_write(cmd.encode_packet())?;
+        
+        loop {
+            match self.recv_response()? {
+                Response::Complete { id, error, data } => {
+                    if id != cmd.id() {
+                        return Err(CommunicationFailed(format!("SC64: response id mismatch: {:?} vs expected {:?}", id as char, cmd.id() as char)));
+                    }
+                    if error {
+                        return Err(CommunicationFailed(format!("SC64: command {:?} returned an error", id as char)));
+                    }
+                    
+                    return Ok(data);
+                },
+                Response::Packet { id, data } => self.packets.push_back((id, data)),
+            }
+        }
+    }
+    
+    /// Removes the oldest queued debug packet, if there is one.
+    fn take_debug_packet(&mut self) -> Option<Result<DebugResponse>> {
+        let index = self.packets.iter().position(|(id, _)| *id == b'U')?;
+        let (_, data) = self.packets.remove(index)?;
+        
+        let response = parse_debug_packet(data);
+        if let Ok((kind, ref data)) = response {
+            self.session.receive(kind, data);
+        }
+        
+        Some(response)
+    }
+    
+    /// Receives a single asynchronous packet and queues it.
+    fn recv_packet(&mut self) -> Result<()> {
+        match self.recv_response()? {
+            Response::Packet { id, data } => self.packets.push_back((id, data)),
+            Response::Complete { id, .. } => debug!("Discarding unexpected response to command {:?}", id as char),
+        }
+        
+        Ok(())
+    }
+    
+    fn recv_response(&mut self) -> Result<Response> {
+        let header = self.device_read(8)?;
+        let id = header[3];
+        let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        
+        let kind = &header[0..3];
+        let data = self.device_read(length)?;
+        match kind {
+            b"CMP" => Ok(Response::Complete { id, error: false, data }),
+            b"ERR" => Ok(Response::Complete { id, error: true, data }),
+            b"PKT" => Ok(Response::Packet { id, data }),
+            _ => {
+                std::thread::sleep(Duration::from_millis(5));
+                self.device.purge()?;
+                Err(CommunicationFailed(format!("SC64: unknown response token: {kind:02X?}")))
+            }
+        }
+    }
+    
+    fn device_read(&mut self, length: usize) -> Result<Vec<u8>> {
+        let mut buf = vec![0xFFu8; length];
+        if length == 0 {
+            return Ok(buf);
+        }
+        
+        self.device.read_exact(&mut buf)?;
+        
+        Ok(buf)
+    }
+    
+    fn device_write<D: AsRef<[u8]>>(&mut self, data: D) -> Result<()> {
+        self.device.write_all(data.as_ref())
+    }
+}
+
+
+
+/// Parses the payload of a `U` packet: a header word (datatype in the upper 8 bits, length in the
+/// lower 24 bits), followed by the data itself.
+fn parse_debug_packet(data: Vec<u8>) -> Result<DebugResponse> {
+    if data.len() < 4 {
+        return Err(CommunicationFailed(format!("SC64: debug packet too short: {data:02X?}")));
+    }
+    
+    let kind = DataType::from(data[0]);
+    let length = u32::from_be_bytes([0, data[1], data[2], data[3]]) as usize;
+    let data = data[4..].get(..length)
+        .ok_or_else(|| CommunicationFailed(format!("SC64: debug packet truncated: expected {length} bytes, received {}", data.len() - 4)))?
+        .to_vec();
+    
+    debug!("Received {kind:?} data: {data:02X?}");
+    
+    Ok((kind, data))
+}
+
+/// Gets the address and length of the save data for the given savetype.
+fn save_location(savetype: SaveType) -> Option<(u32, u32)> {
+    let length = savetype.length()?;
+    
+    match savetype {
+        SaveType::Eeprom4Kbit | SaveType::Eeprom16Kbit => Some((EEPROM_ADDRESS, length)),
+        _ => Some((SAVE_ADDRESS, length)),
+    }
+}
+
+/// Gets the SC64 CIC seed value associated with each CIC variant.
+/// 
+/// The lower 8 bits are the seed. Bit 8 selects the checksum algorithm used by the 6101 and 7102.
+/// 
+/// `Cic::Auto` and `Cic::Unknown` will return `None`.
+fn cic_seed(cic: Cic) -> Option<u32> {
+    use Cic::*;
+    
+    match cic {
+        Var6101 => Some(0x13F),
+        Var6102 => Some(0x03F),
+        Var7101 => Some(0x03F),
+        Var7102 => Some(0x13F),
+        VarX103 => Some(0x078),
+        VarX105 => Some(0x091),
+        VarX106 => Some(0x085),
+        Var5101 => Some(0x0AC),
+        Auto | Unknown => None
+    }
+}
+
+fn savetype_index(savetype: SaveType) -> Option<u32> {
+    use SaveType::*;
+    
+    match savetype {
+        Nothing => Some(0),
+        Eeprom4Kbit => Some(1),
+        Eeprom16Kbit => Some(2),
+        Sram256Kbit => Some(3),
+        FlashRam1Mbit => Some(4),
+        Sram768Kbit => Some(5),
+        FlashRam1MbitStadium => Some(4),
+        Auto | Unknown => None,
+    }
+}
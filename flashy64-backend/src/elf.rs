@@ -0,0 +1,248 @@
+//! Support for homebrew ELF files, as produced by libdragon and other N64 toolchains.
+
+use std::fmt::Write;
+use addr2line::Context;
+use addr2line::gimli::{EndianRcSlice, RunTimeEndian};
+use log::debug;
+use object::{Object, ObjectSegment, ObjectSymbol, SymbolKind};
+use crate::carts::Cic;
+use crate::rom;
+use crate::rom::{CHECKSUM_LENGTH, CHECKSUM_START, HEADER_LENGTH, HEADER_MAGIC};
+
+/// First 4 bytes of every ELF file.
+pub const MAGIC: [u8; 4] = *b"\x7FELF";
+
+/// Length of the IPL3 boot code, which directly follows the ROM header.
+pub const IPL3_LENGTH: usize = CHECKSUM_START - HEADER_LENGTH;
+
+/// Largest ROM which any supported cartridge can hold (240 MiB, on a 64drive with extended addressing).
+pub const ROM_MAX_LENGTH: usize = 240 * 1024 * 1024;
+
+/// A function from the ELF's symbol table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    /// Address of the function, as a 32-bit MIPS address.
+    pub address: u32,
+    /// Length of the function in bytes, or 0 if it's unknown.
+    pub size: u32,
+}
+
+/// Looks up the functions and source lines of addresses in a homebrew ELF, to annotate crash logs
+/// and stack traces received from the cartridge.
+pub struct Symbolizer {
+    /// Function symbols, sorted by address.
+    symbols: Vec<Symbol>,
+    /// DWARF line info, if the ELF was built with debug info.
+    context: Option<Context<EndianRcSlice<RunTimeEndian>>>,
+    /// Whether addresses in the ELF are sign-extended to 64 bits.
+    is_64: bool,
+}
+impl Symbolizer {
+    /// Reads the symbol table and DWARF line info of an ELF file. Line info is optional.
+    pub fn parse(data: &[u8]) -> Result<Symbolizer, String> {
+        let file = object::File::parse(data).map_err(|err| format!("invalid ELF file: {err}"))?;
+        
+        let mut symbols: Vec<Symbol> = file.symbols()
+            .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.address() != 0)
+            .filter_map(|symbol| Some(Symbol {
+                name: symbol.name().ok()?.to_owned(),
+                address: symbol.address() as u32,
+                size: symbol.size() as u32,
+            }))
+            .collect();
+        symbols.sort_by_key(|symbol| symbol.address);
+        
+        let context = Context::new(&file).ok();
+        
+        Ok(Symbolizer {
+            symbols,
+            context,
+            is_64: file.is_64(),
+        })
+    }
+    
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+    
+    /// Finds the function containing the address, and the address's offset into it. Functions of
+    /// unknown size are assumed to extend up to the next function.
+    pub fn function(&self, address: u32) -> Option<(&Symbol, u32)> {
+        let index = self.symbols.partition_point(|symbol| symbol.address <= address).checked_sub(1)?;
+        let symbol = &self.symbols[index];
+        let offset = address - symbol.address;
+        if symbol.size != 0 && offset >= symbol.size {
+            return None;
+        }
+        
+        Some((symbol, offset))
+    }
+    
+    /// Finds the source file and line of the address, if the ELF has line info for it.
+    pub fn location(&self, address: u32) -> Option<(String, u32)> {
+        let probe = if self.is_64 { address as i32 as i64 as u64 } else { address as u64 };
+        let location = self.context.as_ref()?.find_location(probe).ok()??;
+        
+        Some((location.file?.to_owned(), location.line?))
+    }
+    
+    /// Describes the address as `function+0xoffset at file:line`, with whichever parts are known.
+    pub fn describe(&self, address: u32) -> Option<String> {
+        let function = self.function(address);
+        let location = self.location(address);
+        
+        let mut description = match function {
+            Some((symbol, 0)) => symbol.name.clone(),
+            Some((symbol, offset)) => format!("{}+{offset:#X}", symbol.name),
+            None => String::new(),
+        };
+        if let Some((file, line)) = location {
+            if !description.is_empty() {
+                description.push_str(" at ");
+            }
+            let _ = write!(description, "{file}:{line}");
+        }
+        
+        (!description.is_empty()).then_some(description)
+    }
+    
+    /// Appends a description (e.g. ` <main+0x1C at main.c:42>`) after every known address in the
+    /// text. Addresses are 8 hex digits in KSEG0 or KSEG1 (`0x80000000`-`0xBFFFFFFF`), optionally
+    /// prefixed with `0x`, or sign-extended to 16 digits.
+    pub fn annotate(&self, text: &str) -> String {
+        let mut annotated = String::with_capacity(text.len());
+        let mut rest = text;
+        
+        while let Some(start) = rest.find(|c: char| c.is_ascii_alphanumeric()) {
+            let (before, from_word) = rest.split_at(start);
+            let length = from_word.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(from_word.len());
+            let (word, after) = from_word.split_at(length);
+            annotated.push_str(before);
+            annotated.push_str(word);
+            rest = after;
+            
+            if let Some(description) = parse_address(word).and_then(|address| self.describe(address)) {
+                let _ = write!(annotated, " <{description}>");
+            }
+        }
+        annotated.push_str(rest);
+        
+        annotated
+    }
+}
+
+
+
+/// Checks whether the data starts with the ELF magic.
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Builds a bootable ROM image from a homebrew ELF.
+/// 
+/// The ROM begins with a header and IPL3 taken from `boot`, which is either an existing ROM (whose
+/// header is used as a template), or a bare IPL3 of [`IPL3_LENGTH`] bytes (in which case a default
+/// header named `name` is used). The ELF's loadable segments follow at [`CHECKSUM_START`], laid out
+/// by address with any gaps zero-filled, and the ROM may be at most [`ROM_MAX_LENGTH`] bytes. The IPL3 copies the first MiB of them to the boot address
+/// and jumps there, so the ELF's entry point must be its lowest loaded address.
+/// 
+/// The boot checksum is calculated for `cic`. [`Cic::Auto`] detects it from the IPL3, and if it's
+/// unknown, the checksum is left unset.
+pub fn build_rom(elf: &[u8], boot: &[u8], cic: Cic, name: &str) -> Result<Vec<u8>, String> {
+    let file = object::File::parse(elf).map_err(|err| format!("invalid ELF file: {err}"))?;
+    
+    let mut segments = vec![];
+    for segment in file.segments() {
+        let data = segment.data().map_err(|err| format!("invalid ELF segment: {err}"))?;
+        if !data.is_empty() {
+            segments.push((segment.address() as u32, data));
+        }
+    }
+    let Some(base) = segments.iter().map(|(address, _)| *address).min() else {
+        return Err("ELF file has no loadable segments".into());
+    };
+    let entry = file.entry() as u32;
+    if entry != base {
+        return Err(format!("ELF entry point {entry:#010X} isn't at the start of its loaded segments ({base:#010X}), where the IPL3 starts the game"));
+    }
+    
+    let mut data = boot_header(boot, name)?;
+    for (address, segment) in segments {
+        let offset = CHECKSUM_START + (address - base) as usize;
+        debug!("Placing ELF segment {address:#010X} ({:#X} bytes) at ROM offset {offset:#X}", segment.len());
+        if offset + segment.len() > ROM_MAX_LENGTH {
+            return Err(format!("ELF segment {address:#010X} ({:#X} bytes) would end at ROM offset {:#X} vs maximum ROM length {ROM_MAX_LENGTH:#X}", segment.len(), offset + segment.len()));
+        }
+        if data.len() < offset + segment.len() {
+            data.resize(offset + segment.len(), 0x00);
+        }
+        data[offset..(offset + segment.len())].copy_from_slice(segment);
+    }
+    if data.len() < CHECKSUM_START + CHECKSUM_LENGTH {
+        data.resize(CHECKSUM_START + CHECKSUM_LENGTH, 0x00);
+    }
+    
+    let cic = match cic {
+        Cic::Auto => Cic::from_ipl3(&data[HEADER_LENGTH..CHECKSUM_START]),
+        cic => cic,
+    };
+    // The x103 and x106 IPL3s start the game at an offset from the header's boot address.
+    let boot_address = match cic {
+        Cic::VarX103 => entry.wrapping_sub(0x100000),
+        Cic::VarX106 => entry.wrapping_sub(0x200000),
+        _ => entry,
+    };
+    data[0x08..0x0C].copy_from_slice(&boot_address.to_be_bytes());
+    
+    if let Some(checksum) = rom::checksum(&data, cic) {
+        rom::write_checksum(&mut data, checksum);
+    }
+    
+    Ok(data)
+}
+
+
+
+/// Gets the header and IPL3 which begin the ROM, from either a ROM or a bare IPL3.
+fn boot_header(boot: &[u8], name: &str) -> Result<Vec<u8>, String> {
+    if boot.len() == IPL3_LENGTH {
+        let mut header = vec![0x00; HEADER_LENGTH];
+        header[0x00..0x04].copy_from_slice(&HEADER_MAGIC);
+        // Default clock rate, and libultra 2.0L.
+        header[0x04..0x08].copy_from_slice(&0x0000000Fu32.to_be_bytes());
+        header[0x0C..0x10].copy_from_slice(&0x0000144Cu32.to_be_bytes());
+        
+        let mut padded_name = [b' '; 20];
+        for (dst, src) in padded_name.iter_mut().zip(name.bytes().filter(u8::is_ascii)) {
+            *dst = src;
+        }
+        header[0x20..0x34].copy_from_slice(&padded_name);
+        header[0x3B] = b'N';
+        
+        header.extend_from_slice(boot);
+        return Ok(header);
+    }
+    
+    let boot = rom::normalize(boot);
+    if boot.len() < CHECKSUM_START || boot[0..4] != HEADER_MAGIC {
+        return Err(format!("IPL3 must be a ROM with a valid header, or exactly {IPL3_LENGTH:#X} bytes long ({} bytes)", boot.len()));
+    }
+    
+    Ok(boot[..CHECKSUM_START].to_vec())
+}
+
+
+
+/// Parses a word of text as a KSEG0/KSEG1 address.
+fn parse_address(word: &str) -> Option<u32> {
+    let word = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")).unwrap_or(word);
+    let digits = match word.len() {
+        8 => word,
+        16 => word.strip_prefix("ffffffff").or_else(|| word.strip_prefix("FFFFFFFF"))?,
+        _ => return None,
+    };
+    let address = u32::from_str_radix(digits, 16).ok()?;
+    
+    (0x80000000..0xC0000000).contains(&address).then_some(address)
+}
@@ -2,79 +2,258 @@ extern crate core;
 #[macro_use]
 extern crate lazy_static;
 
-use libftd2xx::{DeviceInfo, Ftdi, FtdiCommon, FtStatus, TimeoutError};
-use log::debug;
+use std::cmp::min;
+use log::{debug, warn};
 use crate::carts::{Cic, SaveType};
+use crate::carts::everdrive::EverDrive;
 use crate::carts::sixtyfourdrive::SixtyFourDrive;
-use crate::unfloader::DebugResponse;
+use crate::carts::summercart64::SummerCart64;
+use crate::progress::{Progress, Tracker};
+use crate::transport::{Identity, Transport};
+use crate::unfloader::{DataType, DebugResponse, ProtocolVersion};
 
 pub mod carts;
+pub mod elf;
+pub mod progress;
+pub mod report;
+pub mod rom;
+pub mod transport;
 pub mod unfloader;
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
-    FtdiStatus(FtStatus),
-    FtdiTimeout(TimeoutError),
+    Transport(String),
+    Timeout,
     
     CommunicationFailed(String),
+    InvalidFirmware(String),
     
     Unsupported,
 }
-impl From<FtStatus> for Error {
-    fn from(value: FtStatus) -> Self {
-        Self::FtdiStatus(value)
-    }
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Description of an opened flashcart, returned by [`Flashcart::info`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CartInfo {
+    /// Name of the cartridge and its hardware revision (e.g. "64drive HW2").
+    pub model: String,
+    /// Firmware version, if the cartridge reports it.
+    pub firmware: Option<String>,
+    /// Hardware variant identifier reported by the cartridge, if any.
+    pub variant: Option<u32>,
+    pub serial_number: String,
+    pub capabilities: Capabilities,
 }
-impl From<TimeoutError> for Error {
-    fn from(value: TimeoutError) -> Self {
-        Self::FtdiTimeout(value)
-    }
+
+/// Features which differ between cartridges, or between revisions of the same cartridge.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// [`Flashcart::set_cic`] is supported.
+    pub set_cic: bool,
+    /// [`Flashcart::set_savetype`] is supported.
+    pub set_savetype: bool,
+    /// EEPROM saves can be accessed with [`Flashcart::download_save`] and [`Flashcart::upload_save`].
+    pub eeprom_saves: bool,
+    /// Largest ROM which can be uploaded, in bytes.
+    pub max_rom_length: usize,
 }
 
-pub type Result<T> = std::result::Result<T, Error>;
+/// Size of the chunks compared by [`Flashcart::verify_rom`].
+pub const VERIFY_CHUNK_SIZE: usize = 0x100000;
 
 pub trait Flashcart {
-    fn upload_rom(&mut self, data: &[u8]) -> Result<()>;
-    fn download_rom(&mut self, length: u32) -> Result<Vec<u8>>;
+    fn upload_rom(&mut self, data: &[u8], progress: Progress) -> Result<()>;
+    fn download_rom(&mut self, length: u32, progress: Progress) -> Result<Vec<u8>>;
+    
+    /// Overwrites part of the already uploaded ROM, starting at `offset`.
+    fn upload_rom_at(&mut self, offset: u32, data: &[u8], progress: Progress) -> Result<()>;
+    
+    /// Downloads part of the ROM, starting at `offset`. Some cartridges return a few extra bytes, to
+    /// fill out a whole word.
+    fn download_rom_at(&mut self, offset: u32, length: u32, progress: Progress) -> Result<Vec<u8>>;
+    
+    /// Reads the uploaded ROM back, and compares it against `data` in chunks of [`VERIFY_CHUNK_SIZE`]
+    /// bytes. Mismatched chunks are uploaded again, and compared once more.
+    /// 
+    /// Returns the offsets of the chunks which had to be uploaded again. Fails if any of them still
+    /// mismatch afterwards. Progress is only reported while reading the ROM back.
+    fn verify_rom(&mut self, data: &[u8], progress: Progress) -> Result<Vec<u32>> {
+        let mut tracker = Tracker::new(progress, data.len());
+        let mut mismatched = vec![];
+        for (i, chunk) in data.chunks(VERIFY_CHUNK_SIZE).enumerate() {
+            let offset = (i * VERIFY_CHUNK_SIZE) as u32;
+            if self.download_rom_at(offset, chunk.len() as u32, None)?.get(..chunk.len()) != Some(chunk) {
+                debug!("ROM mismatch in chunk at offset {offset:#010X}");
+                mismatched.push(offset);
+            }
+            tracker.advance(chunk.len());
+        }
+        
+        let mut failed = vec![];
+        for &offset in &mismatched {
+            let chunk = &data[(offset as usize)..min(offset as usize + VERIFY_CHUNK_SIZE, data.len())];
+            self.upload_rom_at(offset, chunk, None)?;
+            if self.download_rom_at(offset, chunk.len() as u32, None)?.get(..chunk.len()) != Some(chunk) {
+                failed.push(offset);
+            }
+        }
+        
+        if !failed.is_empty() {
+            let offsets: Vec<String> = failed.iter().map(|offset| format!("{offset:#010X}")).collect();
+            return Err(Error::CommunicationFailed(format!("ROM verification failed at offsets: {}", offsets.join(", "))));
+        }
+        
+        Ok(mismatched)
+    }
+    
+    /// Uploads save data for the given savetype. `data` may be shorter than [`SaveType::length`].
+    fn upload_save(&mut self, savetype: SaveType, data: &[u8], progress: Progress) -> Result<()>;
+    
+    /// Downloads all of the save data for the given savetype.
+    fn download_save(&mut self, savetype: SaveType, progress: Progress) -> Result<Vec<u8>>;
     
     fn set_cic(&mut self, cic: Cic) -> Result<()>;
     fn set_savetype(&mut self, savetype: SaveType) -> Result<()>;
     
+    /// Enables or disables extended addressing, which maps ROMs larger than 64 MiB into the N64's
+    /// address space. Cartridges which need it enable it automatically when uploading a large ROM.
+    fn set_extended(&mut self, _enabled: bool) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+    
     fn recv_debug(&mut self) -> Result<DebugResponse>;
-    fn send_debug(&mut self) -> Result<()>;
-    fn info(&mut self) -> Result<DeviceInfo>;
+    
+    /// Non-blocking variant of [`Flashcart::recv_debug`]. Returns `None` if the cartridge hasn't
+    /// started sending a packet yet.
+    fn poll_debug(&mut self) -> Result<Option<DebugResponse>>;
+    fn send_debug(&mut self, kind: DataType, data: &[u8]) -> Result<()>;
+    
+    /// UNFLoader protocol version of the running game, taken from the last [`DataType::Heartbeat`]
+    /// received. Defaults to [`ProtocolVersion::V1`].
+    fn protocol_version(&self) -> ProtocolVersion;
+    
+    fn info(&mut self) -> Result<CartInfo>;
+    
+    /// Upgrades the cartridge's firmware, from the contents of a vendor firmware file. The progress
+    /// covers writing the file to the cartridge, but not flashing it.
+    fn update_firmware(&mut self, _firmware: &[u8], _progress: Progress) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+    
+    /// Starts the uploaded ROM, for cartridges which don't boot it automatically on console reset.
+    fn boot(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
 
+/// Lists all supported flashcarts, using every enabled transport.
+///  
+/// If a device is visible through more than one transport, the first one (D2XX) is used.
 pub fn carts() -> Result<Vec<Box<dyn Flashcart>>> {
     let mut carts = vec![];
     
-    for info in libftd2xx::list_devices()? {
-        debug!("Device detected: {info:?}");
-        match from_info(&info) {
-            Ok(cart) => carts.push(cart),
-            Err(_) => (),
+    for identity in list_devices()? {
+        if let Ok(cart) = from_identity(&identity) {
+            carts.push(cart);
         }
     }
     
     Ok(carts)
 }
 
+/// Lists the identities of all supported devices, without opening them.
+/// 
+/// A transport which can't list its devices (e.g. because its driver isn't installed) is skipped, and
+/// an error is only returned if every transport fails.
+pub fn list_devices() -> Result<Vec<Identity>> {
+    let providers: &[fn() -> Result<Vec<Identity>>] = &[
+        #[cfg(feature = "d2xx")]
+        transport::d2xx::D2xx::list_devices,
+        #[cfg(feature = "libusb")]
+        transport::libusb::Libusb::list_devices,
+    ];
+    
+    let mut identities: Vec<Identity> = vec![];
+    let mut last_error = None;
+    let mut listed = false;
+    for list in providers {
+        let found = match list() {
+            Ok(found) => found,
+            Err(err) => {
+                warn!("Unable to list devices: {err:?}");
+                last_error = Some(err);
+                continue;
+            }
+        };
+        listed = true;
+        
+        for identity in found.into_iter().filter(is_supported) {
+            if !identities.iter().any(|other| other.serial_number == identity.serial_number) {
+                identities.push(identity);
+            }
+        }
+    }
+    
+    match last_error {
+        Some(err) if !listed => Err(err),
+        _ => Ok(identities),
+    }
+}
+
 pub fn from_serial<S: AsRef<str>>(serial: S) -> Result<Box<dyn Flashcart>> {
-    let mut device = Ftdi::with_serial_number(serial.as_ref())?;
-    let info = device.device_info()?;
-    device.close()?;
+    let identity = list_devices()?.into_iter()
+        .find(|identity| identity.serial_number == serial.as_ref())
+        .ok_or(Error::Unsupported)?;
     
-    from_info(&info)
+    from_identity(&identity)
 }
 
-pub fn from_info(info: &DeviceInfo) -> Result<Box<dyn Flashcart>> {
-    match (info.vendor_id, info.product_id, info.description.as_str()) {
-        (0x0403, 0x6010, "64drive USB device A") => Ok(Box::new(SixtyFourDrive::new(Ftdi::with_serial_number(&info.serial_number)?)?)),
-        (0x0403, 0x6014, "64drive USB device") => Ok(Box::new(SixtyFourDrive::new(Ftdi::with_serial_number(&info.serial_number)?)?)),
-        (0x0403, 0x6001, "FT245R USB FIFO") => todo!("everdrive"),
-        (0x0403, 0x6014, "SC64") => todo!("summercart64"),
+/// Opens the device through the first enabled transport which can access it.
+pub fn from_identity(identity: &Identity) -> Result<Box<dyn Flashcart>> {
+    if !is_supported(identity) {
+        return Err(Error::Unsupported);
+    }
+    
+    type Open = fn(&str) -> Result<Box<dyn Flashcart>>;
+    let providers: &[Open] = &[
+        #[cfg(feature = "d2xx")]
+        |serial| from_transport(transport::d2xx::D2xx::open(serial)?),
+        #[cfg(feature = "libusb")]
+        |serial| from_transport(transport::libusb::Libusb::open(serial)?),
+    ];
+    
+    let mut result = Err(Error::Unsupported);
+    for open in providers {
+        result = open(&identity.serial_number);
+        if result.is_ok() {
+            break;
+        }
+    }
+    
+    result
+}
+
+/// Creates the flashcart matching the transport's [`Identity`].
+pub fn from_transport<T: Transport + 'static>(mut transport: T) -> Result<Box<dyn Flashcart>> {
+    let identity = transport.identity()?;
+    
+    match (identity.vendor_id, identity.product_id, identity.description.as_str()) {
+        (0x0403, 0x6010, "64drive USB device A") => Ok(Box::new(SixtyFourDrive::new(transport)?)),
+        (0x0403, 0x6014, "64drive USB device") => Ok(Box::new(SixtyFourDrive::new(transport)?)),
+        (0x0403, 0x6001, "FT245R USB FIFO") => Ok(Box::new(EverDrive::new(transport)?)),
+        (0x0403, 0x6014, "SC64") => Ok(Box::new(SummerCart64::new(transport)?)),
         
         _ => Err(Error::Unsupported)
     }
-}
\ No newline at end of file
+}
+
+fn is_supported(identity: &Identity) -> bool {
+    matches!((identity.vendor_id, identity.product_id, identity.description.as_str()),
+        (0x0403, 0x6010, "64drive USB device A") |
+        (0x0403, 0x6014, "64drive USB device") |
+        (0x0403, 0x6001, "FT245R USB FIFO") |
+        (0x0403, 0x6014, "SC64")
+    )
+}
@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+
+/// Progress of a transfer to or from the cartridge. Reported once before the first chunk, and again
+/// after every chunk.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TransferProgress {
+    pub bytes_done: usize,
+    pub total: usize,
+    /// Average transfer rate so far, in bytes per second.
+    pub rate: f64,
+}
+impl TransferProgress {
+    /// Estimated time until the transfer is complete, based on the average rate so far.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.rate <= 0.0 {
+            return None;
+        }
+        
+        Some(Duration::from_secs_f64(self.total.saturating_sub(self.bytes_done) as f64 / self.rate))
+    }
+    
+    pub fn is_complete(&self) -> bool {
+        self.bytes_done >= self.total
+    }
+}
+
+/// Optional observer for a transfer's progress.
+pub type Progress<'a> = Option<&'a mut dyn FnMut(TransferProgress)>;
+
+/// Keeps track of a transfer's progress, and reports it to the observer.
+pub(crate) struct Tracker<'a> {
+    progress: Progress<'a>,
+    start: Instant,
+    bytes_done: usize,
+    total: usize,
+}
+impl<'a> Tracker<'a> {
+    pub fn new(progress: Progress<'a>, total: usize) -> Self {
+        let mut tracker = Self {
+            progress,
+            start: Instant::now(),
+            bytes_done: 0,
+            total,
+        };
+        tracker.report();
+        
+        tracker
+    }
+    
+    /// Marks another chunk as transferred.
+    pub fn advance(&mut self, length: usize) {
+        self.bytes_done = (self.bytes_done + length).min(self.total);
+        self.report();
+    }
+    
+    fn report(&mut self) {
+        let Some(ref mut progress) = self.progress else { return };
+        
+        let elapsed = self.start.elapsed().as_secs_f64();
+        progress(TransferProgress {
+            bytes_done: self.bytes_done,
+            total: self.total,
+            rate: if elapsed > 0.0 { self.bytes_done as f64 / elapsed } else { 0.0 },
+        });
+    }
+}
@@ -0,0 +1,241 @@
+//! Collects test results printed by test ROMs, and writes them as JUnit XML or JSON reports.
+//! 
+//! Results are read from UNFLoader text, one per line, in the format:
+//! 
+//! ```text
+//! TEST:<suite>:<case>:<PASS|FAIL|SKIP>[:<duration in milliseconds>[:<message>]]
+//! ```
+//! 
+//! For example `TEST:math:sqrt:PASS:0.25` or `TEST:audio:mixer:FAIL:12:expected 0x7FFF`. The suite
+//! and case names may not contain `:`, but the message may. An invalid duration is logged and
+//! treated as 0, so the result is still reported. Any other lines are ignored.
+
+use std::fmt::{Display, Formatter, Write};
+use std::str::FromStr;
+use std::time::Duration;
+use log::warn;
+
+/// Prefix of every test result line.
+pub const PREFIX: &str = "TEST:";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TestStatus {
+    Passed,
+    Failed,
+    Skipped,
+}
+impl Display for TestStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            TestStatus::Passed => "pass",
+            TestStatus::Failed => "fail",
+            TestStatus::Skipped => "skip",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestCase {
+    pub suite: String,
+    pub name: String,
+    pub status: TestStatus,
+    pub duration: Duration,
+    pub message: Option<String>,
+}
+impl TestCase {
+    /// Parses a single test result line. Returns `None` if the line isn't a test result.
+    pub fn parse(line: &str) -> Option<TestCase> {
+        let mut fields = line.trim_end_matches(['\r', '\n']).strip_prefix(PREFIX)?.splitn(5, ':');
+        
+        let suite = fields.next()?.to_owned();
+        let name = fields.next()?.to_owned();
+        let status = match fields.next()?.trim().to_uppercase().as_str() {
+            "PASS" => TestStatus::Passed,
+            "FAIL" => TestStatus::Failed,
+            "SKIP" => TestStatus::Skipped,
+            _ => return None,
+        };
+        let duration = match fields.next().map(str::trim) {
+            Some(ms) if !ms.is_empty() => match ms.parse::<f64>().ok().and_then(|ms| Duration::try_from_secs_f64(ms / 1000.0).ok()) {
+                Some(duration) => duration,
+                None => {
+                    warn!("Invalid duration in test result {suite}:{name}: {ms:?}");
+                    Duration::ZERO
+                }
+            },
+            _ => Duration::ZERO,
+        };
+        let message = fields.next().map(str::to_owned);
+        
+        Some(TestCase {
+            suite,
+            name,
+            status,
+            duration,
+            message,
+        })
+    }
+}
+
+/// Format of a written report.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    JUnit,
+    Json,
+}
+impl FromStr for ReportFormat {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "junit" | "xml" => ReportFormat::JUnit,
+            "json" => ReportFormat::Json,
+            
+            _ => return Err("Accepted values: junit or json".into())
+        })
+    }
+}
+
+/// Test results collected from a stream of text.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub cases: Vec<TestCase>,
+    /// Text received after the last newline.
+    partial: String,
+}
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Adds received text to the report. Lines may be split across any number of calls.
+    pub fn feed(&mut self, text: &str) {
+        self.partial.push_str(text);
+        
+        while let Some(newline) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=newline).collect();
+            self.cases.extend(TestCase::parse(&line));
+        }
+    }
+    
+    /// Parses any text left after the last newline, for when the stream has ended.
+    pub fn finish(&mut self) {
+        let line = std::mem::take(&mut self.partial);
+        self.cases.extend(TestCase::parse(&line));
+    }
+    
+    /// Number of cases with the given status.
+    pub fn count(&self, status: TestStatus) -> usize {
+        self.cases.iter().filter(|case| case.status == status).count()
+    }
+    
+    pub fn write(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::JUnit => self.to_junit(),
+            ReportFormat::Json => self.to_json(),
+        }
+    }
+    
+    /// Formats the report as JUnit XML, with one `<testsuite>` per suite, in the order they first appeared.
+    pub fn to_junit(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(xml, "<testsuites tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+            self.cases.len(), self.count(TestStatus::Failed), self.count(TestStatus::Skipped), total_time(&self.cases));
+        
+        let mut suites: Vec<&str> = vec![];
+        for case in &self.cases {
+            if !suites.contains(&case.suite.as_str()) {
+                suites.push(&case.suite);
+            }
+        }
+        
+        for suite in suites {
+            let cases: Vec<&TestCase> = self.cases.iter().filter(|case| case.suite == suite).collect();
+            let count = |status| cases.iter().filter(|case| case.status == status).count();
+            let _ = writeln!(xml, "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+                escape_xml(suite), cases.len(), count(TestStatus::Failed), count(TestStatus::Skipped), total_time(cases.iter().copied()));
+            
+            for case in cases {
+                let _ = write!(xml, "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                    escape_xml(&case.name), escape_xml(suite), case.duration.as_secs_f64());
+                let message = escape_xml(case.message.as_deref().unwrap_or(""));
+                match case.status {
+                    TestStatus::Passed => xml.push_str("/>\n"),
+                    TestStatus::Failed => { let _ = write!(xml, ">\n      <failure message=\"{message}\"/>\n    </testcase>\n"); },
+                    TestStatus::Skipped => { let _ = write!(xml, ">\n      <skipped message=\"{message}\"/>\n    </testcase>\n"); },
+                }
+            }
+            
+            xml.push_str("  </testsuite>\n");
+        }
+        
+        xml.push_str("</testsuites>\n");
+        xml
+    }
+    
+    /// Formats the report as a JSON object, with the totals and a list of cases.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\n");
+        let _ = writeln!(json, "  \"tests\": {},", self.cases.len());
+        let _ = writeln!(json, "  \"passed\": {},", self.count(TestStatus::Passed));
+        let _ = writeln!(json, "  \"failed\": {},", self.count(TestStatus::Failed));
+        let _ = writeln!(json, "  \"skipped\": {},", self.count(TestStatus::Skipped));
+        json.push_str("  \"cases\": [");
+        
+        for (i, case) in self.cases.iter().enumerate() {
+            let message = match case.message {
+                Some(ref message) => escape_json(message),
+                None => "null".into(),
+            };
+            let _ = write!(json, "{}\n    {{\"suite\": {}, \"name\": {}, \"status\": \"{}\", \"duration_ms\": {}, \"message\": {message}}}",
+                if i == 0 { "" } else { "," }, escape_json(&case.suite), escape_json(&case.name), case.status, case.duration.as_nanos() as f64 / 1_000_000.0);
+        }
+        
+        json.push_str(if self.cases.is_empty() { "]\n}\n" } else { "\n  ]\n}\n" });
+        json
+    }
+}
+
+
+
+fn total_time<'a>(cases: impl IntoIterator<Item = &'a TestCase>) -> f64 {
+    cases.into_iter().map(|case| case.duration.as_secs_f64()).sum()
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters aren't allowed in XML 1.0, even when escaped.
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => escaped.push('?'),
+            c => escaped.push(c),
+        }
+    }
+    
+    escaped
+}
+
+/// Quotes and escapes a JSON string.
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => { let _ = write!(escaped, "\\u{:04x}", c as u32); },
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    
+    escaped
+}
@@ -0,0 +1,272 @@
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use log::debug;
+use crate::carts::Cic;
+
+/// Length of the ROM header, which is followed by the IPL3 boot code.
+pub const HEADER_LENGTH: usize = 0x40;
+
+/// Offset of the first byte covered by the boot checksum, directly after the IPL3.
+pub const CHECKSUM_START: usize = 0x1000;
+/// Number of bytes covered by the boot checksum.
+pub const CHECKSUM_LENGTH: usize = 0x100000;
+
+/// First word of every N64 ROM header (PI domain 1 configuration), as it appears in a big-endian image.
+pub const HEADER_MAGIC: [u8; 4] = [0x80, 0x37, 0x12, 0x40];
+
+/// Byte order of a ROM image, commonly indicated by its file extension.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Native big-endian order, used by the cartridge itself (.z64)
+    BigEndian,
+    /// Each pair of bytes is swapped (.v64)
+    ByteSwapped,
+    /// Each 32-bit word is reversed (.n64)
+    LittleEndian,
+}
+impl Display for ByteOrder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            ByteOrder::BigEndian => "big-endian (z64)",
+            ByteOrder::ByteSwapped => "byte-swapped (v64)",
+            ByteOrder::LittleEndian => "little-endian (n64)",
+        })
+    }
+}
+impl FromStr for ByteOrder {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use ByteOrder::*;
+        
+        Ok(match s.to_lowercase().as_str() {
+            "z64" | "big" => BigEndian,
+            "v64" | "swapped" => ByteSwapped,
+            "n64" | "little" => LittleEndian,
+            
+            _ => return Err("Accepted values: z64, v64, or n64".into())
+        })
+    }
+}
+impl ByteOrder {
+    /// Detects the byte order of a ROM image from its header magic. Returns `None` if the image
+    /// doesn't start with a recognizable header.
+    pub fn detect(data: &[u8]) -> Option<ByteOrder> {
+        let magic: [u8; 4] = data.get(0..4)?.try_into().unwrap();
+        
+        [ByteOrder::BigEndian, ByteOrder::ByteSwapped, ByteOrder::LittleEndian].into_iter()
+            .find(|order| order.reorder_word(HEADER_MAGIC) == magic)
+    }
+    
+    /// Typical file extension for ROM images in this byte order, without the leading dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ByteOrder::BigEndian => "z64",
+            ByteOrder::ByteSwapped => "v64",
+            ByteOrder::LittleEndian => "n64",
+        }
+    }
+    
+    /// Converts a single big-endian word into this byte order. Every conversion is its own inverse,
+    /// so this also converts a word in this byte order back to big-endian.
+    fn reorder_word(&self, [a, b, c, d]: [u8; 4]) -> [u8; 4] {
+        match self {
+            ByteOrder::BigEndian => [a, b, c, d],
+            ByteOrder::ByteSwapped => [b, a, d, c],
+            ByteOrder::LittleEndian => [d, c, b, a],
+        }
+    }
+}
+/// The 0x40 byte header at the start of every N64 ROM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomHeader {
+    /// PI domain 1 timing configuration. Always [`HEADER_MAGIC`] in a valid header.
+    pub pi_config: u32,
+    /// Clock rate override. Zero means the default rate is used.
+    pub clock_rate: u32,
+    /// Address in RDRAM where the game's code is loaded and started by the IPL3.
+    pub boot_address: u32,
+    pub libultra_version: u32,
+    /// First half of the checksum calculated by the IPL3.
+    pub crc1: u32,
+    /// Second half of the checksum calculated by the IPL3.
+    pub crc2: u32,
+    /// Internal name, with padding removed.
+    pub name: String,
+    /// 'N' for cartridges, 'D' for 64DD disks, 'C' for cartridges with 64DD expansions, 'E' for 64DD
+    /// expansions, or 'Z' for Aleck64 arcade boards.
+    pub media_format: u8,
+    /// Two character game identifier.
+    pub cartridge_id: [u8; 2],
+    /// Region or language code.
+    pub region: u8,
+    pub revision: u8,
+}
+impl RomHeader {
+    /// Parses the header at the start of a ROM image, in any byte order.
+    /// 
+    /// Fails if the data is too short, or doesn't begin with [`HEADER_MAGIC`].
+    pub fn parse(data: &[u8]) -> Result<RomHeader, String> {
+        let Some(header) = data.get(0..HEADER_LENGTH) else {
+            return Err(format!("ROM is too small to contain a header: {} bytes (min {HEADER_LENGTH})", data.len()));
+        };
+        let header = normalize(header);
+        if header[0..4] != HEADER_MAGIC {
+            return Err(format!("ROM header magic mismatch: {:02X?} vs expected {HEADER_MAGIC:02X?}", &header[0..4]));
+        }
+        
+        let word = |offset: usize| u32::from_be_bytes(header[offset..(offset + 4)].try_into().unwrap());
+        
+        Ok(RomHeader {
+            pi_config: word(0x00),
+            clock_rate: word(0x04),
+            boot_address: word(0x08),
+            libultra_version: word(0x0C),
+            crc1: word(0x10),
+            crc2: word(0x14),
+            name: String::from_utf8_lossy(&header[0x20..0x34]).trim_end_matches([' ', '\0']).to_owned(),
+            media_format: header[0x3B],
+            cartridge_id: [header[0x3C], header[0x3D]],
+            region: header[0x3E],
+            revision: header[0x3F],
+        })
+    }
+    
+    /// The four character game ID (e.g. "NSME"), made up of the media format, cartridge ID, and region.
+    pub fn game_id(&self) -> String {
+        [self.media_format, self.cartridge_id[0], self.cartridge_id[1], self.region].iter()
+            .map(|&c| if c.is_ascii_graphic() { c as char } else { '?' })
+            .collect()
+    }
+    
+    pub fn media_name(&self) -> Option<&'static str> {
+        Some(match self.media_format {
+            b'N' => "Cartridge",
+            b'D' => "64DD disk",
+            b'C' => "Cartridge with 64DD expansion",
+            b'E' => "64DD expansion",
+            b'Z' => "Aleck64",
+            _ => return None,
+        })
+    }
+    
+    pub fn region_name(&self) -> Option<&'static str> {
+        Some(match self.region {
+            b'A' => "All regions",
+            b'B' => "Brazil",
+            b'C' => "China",
+            b'D' => "Germany",
+            b'E' => "North America",
+            b'F' => "France",
+            b'G' => "Gateway 64 (NTSC)",
+            b'H' => "Netherlands",
+            b'I' => "Italy",
+            b'J' => "Japan",
+            b'K' => "Korea",
+            b'L' => "Gateway 64 (PAL)",
+            b'N' => "Canada",
+            b'P' | b'X' | b'Y' | b'Z' => "Europe",
+            b'S' => "Spain",
+            b'U' => "Australia",
+            b'W' => "Scandinavia",
+            _ => return None,
+        })
+    }
+}
+
+
+
+/// Converts ROM data from one byte order to another.
+/// 
+/// Any trailing bytes which don't make up a whole 32-bit word are left as-is.
+pub fn convert(data: &[u8], from: ByteOrder, to: ByteOrder) -> Vec<u8> {
+    let mut data = data.to_vec();
+    if from == to {
+        return data;
+    }
+    
+    for word in data.chunks_exact_mut(4) {
+        let big_endian = from.reorder_word(word.try_into().unwrap());
+        word.copy_from_slice(&to.reorder_word(big_endian));
+    }
+    
+    data
+}
+
+/// Converts ROM data into big-endian (z64) byte order, which is what the cartridge expects.
+/// 
+/// Data is borrowed unchanged if it's already big-endian, or if its byte order can't be detected.
+pub fn normalize(data: &[u8]) -> Cow<'_, [u8]> {
+    match ByteOrder::detect(data) {
+        Some(ByteOrder::BigEndian) | None => Cow::Borrowed(data),
+        Some(order) => {
+            debug!("Converting ROM from {order} to big-endian.");
+            Cow::Owned(convert(data, order, ByteOrder::BigEndian))
+        }
+    }
+}
+
+/// Calculates the CRC1/CRC2 boot checksum, which the IPL3 of the given CIC verifies before starting the game.
+/// 
+/// ROMs which don't reach the end of the checksummed area are treated as if they were zero-padded.
+/// Returns `None` for [`Cic::Auto`] and [`Cic::Unknown`].
+pub fn checksum(data: &[u8], cic: Cic) -> Option<(u32, u32)> {
+    let seed = checksum_seed(cic)?;
+    
+    let mut data = normalize(data).into_owned();
+    if data.len() < CHECKSUM_START + CHECKSUM_LENGTH {
+        data.resize(CHECKSUM_START + CHECKSUM_LENGTH, 0x00);
+    }
+    let word = |offset: usize| u32::from_be_bytes(data[offset..(offset + 4)].try_into().unwrap());
+    
+    let (mut t1, mut t2, mut t3, mut t4, mut t5, mut t6) = (seed, seed, seed, seed, seed, seed);
+    for offset in (CHECKSUM_START..(CHECKSUM_START + CHECKSUM_LENGTH)).step_by(4) {
+        let d = word(offset);
+        if t6.checked_add(d).is_none() {
+            t4 = t4.wrapping_add(1);
+        }
+        t6 = t6.wrapping_add(d);
+        t3 ^= d;
+        
+        let r = d.rotate_left(d & 0x1F);
+        t5 = t5.wrapping_add(r);
+        if t2 > d {
+            t2 ^= r;
+        } else {
+            t2 ^= t6 ^ d;
+        }
+        
+        t1 = match cic {
+            // The x105 IPL3 mixes in words from its own code, instead of the running sum.
+            Cic::VarX105 => t1.wrapping_add(word(0x750 + (offset & 0xFF)) ^ d),
+            _ => t1.wrapping_add(t5 ^ d),
+        };
+    }
+    
+    Some(match cic {
+        Cic::VarX103 => ((t6 ^ t4).wrapping_add(t3), (t5 ^ t2).wrapping_add(t1)),
+        Cic::VarX106 => (t6.wrapping_mul(t4).wrapping_add(t3), t5.wrapping_mul(t2).wrapping_add(t1)),
+        _ => (t6 ^ t4 ^ t3, t5 ^ t2 ^ t1),
+    })
+}
+
+/// Writes the CRC1/CRC2 boot checksum into the header of a big-endian ROM image.
+pub fn write_checksum(data: &mut [u8], (crc1, crc2): (u32, u32)) {
+    data[0x10..0x14].copy_from_slice(&crc1.to_be_bytes());
+    data[0x14..0x18].copy_from_slice(&crc2.to_be_bytes());
+}
+
+/// Initial value of the checksum registers. Each IPL3 derives it from the seed byte stored in its CIC.
+fn checksum_seed(cic: Cic) -> Option<u32> {
+    use Cic::*;
+    
+    match cic {
+        Var6101 | Var6102 | Var7101 | Var7102 => Some(0xF8CA4DDC),
+        VarX103 => Some(0xA3886759),
+        VarX105 => Some(0xDF26F436),
+        VarX106 => Some(0x1FEA617A),
+        Var5101 => Some(0xB77DA7DD),
+        Auto | Unknown => None,
+    }
+}
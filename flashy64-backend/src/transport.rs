@@ -0,0 +1,66 @@
+use std::time::Duration;
+use crate::{Error, Result};
+
+#[cfg(feature = "d2xx")]
+pub mod d2xx;
+#[cfg(feature = "libusb")]
+pub mod libusb;
+#[cfg(feature = "sim")]
+pub mod sim;
+
+/// USB descriptor information, used to determine which kind of cartridge is connected.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct Identity {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial_number: String,
+    pub description: String,
+}
+impl Identity {
+    /// Names the first channel (A) of a multi-channel FTDI chip the way D2XX does, by appending "A"
+    /// to the serial number and " A" to the description. This keeps the same device from being
+    /// listed twice when several transports are enabled.
+    pub fn channel_a(mut self) -> Self {
+        self.serial_number.push('A');
+        self.description.push_str(" A");
+        
+        self
+    }
+}
+
+/// A byte stream to and from a cartridge.
+/// 
+/// Every flashcart supported by this crate uses an FTDI USB chip, so a few FTDI-specific operations
+/// are included. Transports which can't perform them should keep the default implementations.
+pub trait Transport {
+    /// Reads exactly `buf.len()` bytes, or fails with [`Error::Timeout`] if the read timeout elapses first.
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()>;
+    
+    /// Writes all of `data`, or fails with [`Error::Timeout`] if the write timeout elapses first.
+    fn write_all(&mut self, data: &[u8]) -> Result<()>;
+    
+    /// Number of bytes which can be read without blocking.
+    fn bytes_available(&mut self) -> Result<usize>;
+    
+    /// Discards any data in the receive and transmit buffers.
+    fn purge(&mut self) -> Result<()>;
+    
+    fn set_timeouts(&mut self, read: Duration, write: Duration) -> Result<()>;
+    
+    fn identity(&mut self) -> Result<Identity>;
+    
+    /// Switches the FTDI chip into synchronous FIFO mode.
+    fn set_sync_fifo(&mut self) -> Result<()> {
+        Ok(())
+    }
+    
+    /// Sets the state of the DTR modem control line.
+    fn set_dtr(&mut self, _state: bool) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+    
+    /// Gets the state of the DSR modem control line.
+    fn dsr(&mut self) -> Result<bool> {
+        Err(Error::Unsupported)
+    }
+}
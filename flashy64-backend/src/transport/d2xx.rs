@@ -0,0 +1,99 @@
+use std::time::Duration;
+use libftd2xx::{BitMode, DeviceInfo, Ftdi, FtdiCommon, FtStatus, TimeoutError};
+use log::debug;
+use crate::{Error, Result};
+use crate::transport::{Identity, Transport};
+
+impl From<FtStatus> for Error {
+    fn from(value: FtStatus) -> Self {
+        Self::Transport(format!("D2XX: {value:?}"))
+    }
+}
+impl From<TimeoutError> for Error {
+    fn from(value: TimeoutError) -> Self {
+        match value {
+            TimeoutError::FtStatus(status) => status.into(),
+            TimeoutError::Timeout { .. } => Self::Timeout,
+        }
+    }
+}
+impl From<DeviceInfo> for Identity {
+    fn from(value: DeviceInfo) -> Self {
+        Self {
+            vendor_id: value.vendor_id,
+            product_id: value.product_id,
+            serial_number: value.serial_number,
+            description: value.description,
+        }
+    }
+}
+
+/// Transport using FTDI's proprietary D2XX driver.
+#[derive(Debug)]
+pub struct D2xx {
+    device: Ftdi,
+}
+impl Transport for D2xx {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.device.read_all(buf).map_err(|err| err.into())
+    }
+    
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        self.device.write_all(data).map_err(|err| err.into())
+    }
+    
+    fn bytes_available(&mut self) -> Result<usize> {
+        self.device.queue_status().map_err(|err| err.into())
+    }
+    
+    fn purge(&mut self) -> Result<()> {
+        self.device.purge_all().map_err(|err| err.into())
+    }
+    
+    fn set_timeouts(&mut self, read: Duration, write: Duration) -> Result<()> {
+        self.device.set_timeouts(read, write).map_err(|err| err.into())
+    }
+    
+    fn identity(&mut self) -> Result<Identity> {
+        Ok(self.device.device_info()?.into())
+    }
+    
+    fn set_sync_fifo(&mut self) -> Result<()> {
+        self.device.set_bit_mode(0xFF, BitMode::Reset)?;
+        self.device.set_bit_mode(0xFF, BitMode::SyncFifo)?;
+        
+        Ok(())
+    }
+    
+    fn set_dtr(&mut self, state: bool) -> Result<()> {
+        match state {
+            true => self.device.set_dtr()?,
+            false => self.device.clear_dtr()?,
+        }
+        
+        Ok(())
+    }
+    
+    fn dsr(&mut self) -> Result<bool> {
+        Ok(self.device.modem_status()?.data_set_ready())
+    }
+}
+impl D2xx {
+    /// Opens the device with the given serial number, and resets it.
+    pub fn open<S: AsRef<str>>(serial: S) -> Result<Self> {
+        let mut device = Ftdi::with_serial_number(serial.as_ref())?;
+        device.reset().unwrap_or_default();
+        
+        Ok(Self {
+            device,
+        })
+    }
+    
+    /// Lists all devices visible to the D2XX driver, including ones which aren't flashcarts.
+    pub fn list_devices() -> Result<Vec<Identity>> {
+        Ok(libftd2xx::list_devices()?.into_iter()
+            .inspect(|info| debug!("Device detected: {info:?}"))
+            .map(Identity::from)
+            .collect())
+    }
+}
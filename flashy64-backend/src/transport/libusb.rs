@@ -0,0 +1,246 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use log::debug;
+use rusb::{Device, DeviceHandle, Direction, GlobalContext, Recipient, RequestType, TransferType, UsbContext};
+use crate::{Error, Result};
+use crate::transport::{Identity, Transport};
+
+const FTDI_VENDOR_ID: u16 = 0x0403;
+
+const SIO_RESET: u8 = 0x00;
+const SIO_SET_MODEM_CTRL: u8 = 0x01;
+const SIO_POLL_MODEM_STATUS: u8 = 0x05;
+const SIO_SET_LATENCY_TIMER: u8 = 0x09;
+const SIO_SET_BITMODE: u8 = 0x0B;
+
+const SIO_RESET_SIO: u16 = 0;
+const SIO_RESET_PURGE_RX: u16 = 1;
+const SIO_RESET_PURGE_TX: u16 = 2;
+
+const BITMODE_RESET: u16 = 0x00;
+const BITMODE_SYNCFF: u16 = 0x40;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl From<rusb::Error> for Error {
+    fn from(value: rusb::Error) -> Self {
+        match value {
+            rusb::Error::Timeout => Self::Timeout,
+            _ => Self::Transport(format!("libusb: {value}")),
+        }
+    }
+}
+
+/// Transport which talks to the FTDI chip directly through libusb, without any vendor driver.
+/// 
+/// Only the chip's first interface (channel A) is used.
+pub struct Libusb {
+    handle: DeviceHandle<GlobalContext>,
+    identity: Identity,
+    in_endpoint: u8,
+    out_endpoint: u8,
+    /// Maximum packet size of the IN endpoint. The FTDI chip prefixes every packet with 2 modem status bytes.
+    packet_size: usize,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    /// Received data, with the modem status bytes already removed.
+    buffer: VecDeque<u8>,
+}
+impl Transport for Libusb {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        let start = Instant::now();
+        while self.buffer.len() < buf.len() {
+            let remaining = self.read_timeout.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                return Err(Error::Timeout);
+            }
+            
+            self.fill_buffer(remaining)?;
+        }
+        
+        let length = buf.len();
+        for (dst, src) in buf.iter_mut().zip(self.buffer.drain(..length)) {
+            *dst = src;
+        }
+        
+        Ok(())
+    }
+    
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        let start = Instant::now();
+        let mut written = 0;
+        while written < data.len() {
+            let remaining = self.write_timeout.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                return Err(Error::Timeout);
+            }
+            
+            written += self.handle.write_bulk(self.out_endpoint, &data[written..], remaining)?;
+        }
+        
+        Ok(())
+    }
+    
+    fn bytes_available(&mut self) -> Result<usize> {
+        if self.buffer.is_empty() {
+            self.fill_buffer(Duration::from_millis(1))?;
+        }
+        
+        Ok(self.buffer.len())
+    }
+    
+    fn purge(&mut self) -> Result<()> {
+        self.control_out(SIO_RESET, SIO_RESET_PURGE_RX)?;
+        self.control_out(SIO_RESET, SIO_RESET_PURGE_TX)?;
+        self.buffer.clear();
+        
+        Ok(())
+    }
+    
+    fn set_timeouts(&mut self, read: Duration, write: Duration) -> Result<()> {
+        self.read_timeout = read;
+        self.write_timeout = write;
+        
+        Ok(())
+    }
+    
+    fn identity(&mut self) -> Result<Identity> {
+        Ok(self.identity.clone())
+    }
+    
+    fn set_sync_fifo(&mut self) -> Result<()> {
+        self.control_out(SIO_SET_BITMODE, (BITMODE_RESET << 8) | 0xFF)?;
+        self.control_out(SIO_SET_BITMODE, (BITMODE_SYNCFF << 8) | 0xFF)?;
+        
+        Ok(())
+    }
+    
+    fn set_dtr(&mut self, state: bool) -> Result<()> {
+        self.control_out(SIO_SET_MODEM_CTRL, if state { 0x0101 } else { 0x0100 })
+    }
+    
+    fn dsr(&mut self) -> Result<bool> {
+        let mut status = [0u8; 2];
+        let request_type = rusb::request_type(Direction::In, RequestType::Vendor, Recipient::Device);
+        self.handle.read_control(request_type, SIO_POLL_MODEM_STATUS, 0, 1, &mut status, CONTROL_TIMEOUT)?;
+        
+        Ok(status[0] & 0x20 != 0)
+    }
+}
+impl Libusb {
+    /// Opens the FTDI device with the given serial number, and resets it.
+    /// 
+    /// On Linux, the `ftdi_sio` kernel driver is detached from the device while it's open.
+    pub fn open<S: AsRef<str>>(serial: S) -> Result<Self> {
+        for device in rusb::devices()?.iter() {
+            match identity(&device) {
+                Ok(identity) if identity.serial_number == serial.as_ref() => return Self::open_device(device, identity),
+                _ => (),
+            }
+        }
+        
+        Err(Error::Transport(format!("libusb: no device with serial number {}", serial.as_ref())))
+    }
+    
+    /// Lists all FTDI devices visible to libusb, including ones which aren't flashcarts.
+    pub fn list_devices() -> Result<Vec<Identity>> {
+        let mut identities = vec![];
+        for device in rusb::devices()?.iter() {
+            match identity(&device) {
+                Ok(identity) => {
+                    debug!("Device detected: {identity:?}");
+                    identities.push(identity);
+                },
+                Err(err) => debug!("Skipping USB device {:03}:{:03}: {err:?}", device.bus_number(), device.address()),
+            }
+        }
+        
+        Ok(identities)
+    }
+    
+    fn open_device(device: Device<GlobalContext>, identity: Identity) -> Result<Self> {
+        let config = device.active_config_descriptor()?;
+        let interface = config.interfaces().next()
+            .and_then(|interface| interface.descriptors().next())
+            .ok_or_else(|| Error::Transport("libusb: device has no interfaces".into()))?;
+        
+        let mut in_endpoint = None;
+        let mut out_endpoint = None;
+        for endpoint in interface.endpoint_descriptors().filter(|endpoint| endpoint.transfer_type() == TransferType::Bulk) {
+            match endpoint.direction() {
+                Direction::In => in_endpoint = Some((endpoint.address(), endpoint.max_packet_size() as usize)),
+                Direction::Out => out_endpoint = Some(endpoint.address()),
+            }
+        }
+        let ((in_endpoint, packet_size), out_endpoint) = in_endpoint.zip(out_endpoint)
+            .ok_or_else(|| Error::Transport("libusb: device is missing bulk endpoints".into()))?;
+        
+        let handle = device.open()?;
+        handle.set_auto_detach_kernel_driver(true).unwrap_or_default();
+        handle.claim_interface(interface.interface_number())?;
+        
+        let mut transport = Self {
+            handle,
+            identity,
+            in_endpoint,
+            out_endpoint,
+            packet_size,
+            read_timeout: Duration::from_secs(10),
+            write_timeout: Duration::from_secs(10),
+            buffer: VecDeque::new(),
+        };
+        
+        transport.control_out(SIO_RESET, SIO_RESET_SIO)?;
+        transport.control_out(SIO_SET_LATENCY_TIMER, 2)?;
+        
+        Ok(transport)
+    }
+    
+    /// Performs a single bulk read, and appends the received data to the buffer.
+    fn fill_buffer(&mut self, timeout: Duration) -> Result<()> {
+        let mut buf = vec![0u8; self.packet_size * 64];
+        let length = match self.handle.read_bulk(self.in_endpoint, &mut buf, timeout) {
+            Ok(length) => length,
+            Err(rusb::Error::Timeout) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        
+        for packet in buf[..length].chunks(self.packet_size) {
+            self.buffer.extend(packet.iter().skip(2));
+        }
+        
+        Ok(())
+    }
+    
+    fn control_out(&mut self, request: u8, value: u16) -> Result<()> {
+        let request_type = rusb::request_type(Direction::Out, RequestType::Vendor, Recipient::Device);
+        self.handle.write_control(request_type, request, value, 1, &[], CONTROL_TIMEOUT)?;
+        
+        Ok(())
+    }
+}
+
+
+
+/// Reads the USB descriptors of an FTDI device.
+/// 
+/// Multi-channel chips are named after their first channel, to match the naming used by D2XX.
+fn identity<T: UsbContext>(device: &Device<T>) -> Result<Identity> {
+    let descriptor = device.device_descriptor()?;
+    if descriptor.vendor_id() != FTDI_VENDOR_ID {
+        return Err(Error::Unsupported);
+    }
+    
+    let handle = device.open()?;
+    let identity = Identity {
+        vendor_id: descriptor.vendor_id(),
+        product_id: descriptor.product_id(),
+        serial_number: handle.read_serial_number_string_ascii(&descriptor)?,
+        description: handle.read_product_string_ascii(&descriptor)?,
+    };
+    
+    Ok(match device.active_config_descriptor()?.num_interfaces() > 1 {
+        true => identity.channel_a(),
+        false => identity,
+    })
+}
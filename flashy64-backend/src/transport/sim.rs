@@ -0,0 +1,318 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use bytes::{BufMut, BytesMut};
+use crate::{Error, Result};
+use crate::carts::sixtyfourdrive::{Model, UpgradeStatus};
+use crate::transport::{Identity, Transport};
+use crate::unfloader;
+use crate::unfloader::{DataType, DebugResponse, Session};
+
+/// A command received by the simulated 64drive, as seen on the wire.
+#[derive(Clone, PartialEq, Debug)]
+pub enum SimCommand {
+    LoadFromPc {
+        addr: u32,
+        bank_id_len: u32,
+    },
+    DumpToPc {
+        addr: u32,
+        bank_id_len: u32,
+    },
+    TargetSideFifo(DebugResponse),
+    SetSaveType(u32),
+    SetCicType(u32),
+    SetCiExtended(u32),
+    VersionRequest,
+    UpgradeStart,
+    UpgradeReport,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    identity: Identity,
+    version: [u8; 8],
+    /// Bytes written by the host which don't form a complete command yet.
+    pending: Vec<u8>,
+    /// Bytes waiting to be read by the host.
+    rx: VecDeque<u8>,
+    banks: HashMap<u8, Vec<u8>>,
+    /// Bank addresses which are corrupted whenever they're written to.
+    corrupted: HashSet<(u8, usize)>,
+    log: Vec<SimCommand>,
+    /// Statuses reported during a firmware upgrade, in order. The last one is repeated.
+    upgrade: VecDeque<UpgradeStatus>,
+    upgrade_result: Option<UpgradeStatus>,
+    /// State of the simulated game's USB library, whose protocol version changes when it sends a heartbeat.
+    session: Session,
+}
+
+/// In-memory simulation of a 64drive, for testing without hardware.
+/// 
+/// Commands written to the transport are decoded and applied to the simulated ROM and save banks,
+/// and responses are queued to be read back. Clones share the same state, so a test can keep one
+/// clone to inspect the simulation while the cartridge owns another.
+/// 
+/// # Example
+/// ```
+/// use flashy64_backend::Flashcart;
+/// use flashy64_backend::carts::sixtyfourdrive::{Model, SixtyFourDrive};
+/// use flashy64_backend::transport::sim::SimSixtyFourDrive;
+/// 
+/// let sim = SimSixtyFourDrive::new(Model::HW2);
+/// let mut cart = SixtyFourDrive::new(sim.clone()).unwrap();
+/// 
+/// cart.upload_rom(&[1, 2, 3, 4], None).unwrap();
+/// assert_eq!(&sim.bank(1)[0..4], &[1, 2, 3, 4]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct SimSixtyFourDrive {
+    state: Arc<Mutex<State>>,
+}
+impl Transport for SimSixtyFourDrive {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        let mut state = self.state();
+        if state.rx.len() < buf.len() {
+            return Err(Error::Timeout);
+        }
+        
+        let length = buf.len();
+        for (dst, src) in buf.iter_mut().zip(state.rx.drain(..length)) {
+            *dst = src;
+        }
+        
+        Ok(())
+    }
+    
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        let mut state = self.state();
+        state.pending.extend_from_slice(data);
+        while state.process_command()? {}
+        
+        Ok(())
+    }
+    
+    fn bytes_available(&mut self) -> Result<usize> {
+        Ok(self.state().rx.len())
+    }
+    
+    fn purge(&mut self) -> Result<()> {
+        let mut state = self.state();
+        state.pending.clear();
+        state.rx.clear();
+        
+        Ok(())
+    }
+    
+    fn set_timeouts(&mut self, _read: Duration, _write: Duration) -> Result<()> {
+        Ok(())
+    }
+    
+    fn identity(&mut self) -> Result<Identity> {
+        Ok(self.state().identity.clone())
+    }
+}
+impl SimSixtyFourDrive {
+    pub fn new(model: Model) -> Self {
+        let identity = match model {
+            Model::HW1 => Identity {
+                vendor_id: 0x0403,
+                product_id: 0x6010,
+                serial_number: "SIM64HW1".into(),
+                description: "64drive USB device A".into(),
+            },
+            Model::HW2 => Identity {
+                vendor_id: 0x0403,
+                product_id: 0x6014,
+                serial_number: "SIM64HW2".into(),
+                description: "64drive USB device".into(),
+            },
+        };
+        
+        let variant: &[u8; 2] = match model {
+            Model::HW1 => b"A2",
+            Model::HW2 => b"B2",
+        };
+        
+        Self {
+            state: Arc::new(Mutex::new(State {
+                identity,
+                version: [0, 0, variant[0], variant[1], 0, 0, 0, 205],
+                ..Default::default()
+            })),
+        }
+    }
+    
+    /// Sets the 8 byte response to [`Command::VersionRequest`](crate::carts::sixtyfourdrive::Command::VersionRequest).
+    pub fn set_version(&self, version: [u8; 8]) {
+        self.state().version = version;
+    }
+    
+    /// Queues a UNFLoader debug frame (`DMA@`, header, data, `CMPH`), as if it was sent by the N64.
+    /// The data is padded according to the protocol version of the last injected heartbeat.
+    pub fn inject_debug(&self, kind: DataType, data: &[u8]) {
+        self.state().session.receive(kind, data);
+        let protocol = self.state().session.protocol();
+        
+        let mut frame = BytesMut::from(b"DMA@".as_ref());
+        frame.put_u32(unfloader::encode_header(kind, data.len()));
+        frame.put_slice(data);
+        frame.put_bytes(0x00, protocol.data_padding(data.len()));
+        frame.put_slice(b"CMPH");
+        
+        self.inject_raw(&frame);
+    }
+    
+    /// Queues arbitrary bytes to be read by the host.
+    pub fn inject_raw(&self, data: &[u8]) {
+        self.state().rx.extend(data);
+    }
+    
+    /// Contents of the given bank (1 = ROM, 2-5 = SRAM/FlashRAM, 6 = EEPROM). Banks which were never
+    /// written to are empty.
+    pub fn bank(&self, bank: u8) -> Vec<u8> {
+        self.state().banks.get(&bank).cloned().unwrap_or_default()
+    }
+    
+    /// Overwrites the contents of a bank.
+    pub fn set_bank(&self, bank: u8, data: Vec<u8>) {
+        self.state().banks.insert(bank, data);
+    }
+    
+    /// Makes every later write to `addr` in the given bank store a corrupted byte, as if the USB
+    /// connection was unreliable.
+    pub fn corrupt_writes(&self, bank: u8, addr: usize) {
+        self.state().corrupted.insert((bank, addr));
+    }
+    
+    /// Makes the next firmware upgrade end with the given status, instead of [`UpgradeStatus::Success`].
+    pub fn set_upgrade_result(&self, status: UpgradeStatus) {
+        self.state().upgrade_result = Some(status);
+    }
+    
+    /// Every command received so far, in order.
+    pub fn log(&self) -> Vec<SimCommand> {
+        self.state().log.clone()
+    }
+    
+    /// Forgets all previously received commands.
+    pub fn clear_log(&self) {
+        self.state().log.clear();
+    }
+    
+    /// Debug packets sent by the host through the target side FIFO, including any padding.
+    pub fn sent_debug(&self) -> Vec<DebugResponse> {
+        self.state().log.iter().filter_map(|cmd| match cmd {
+            SimCommand::TargetSideFifo(packet) => Some(packet.clone()),
+            _ => None,
+        }).collect()
+    }
+    
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+impl State {
+    /// Decodes and applies the first pending command. Returns `false` if more bytes are needed.
+    fn process_command(&mut self) -> Result<bool> {
+        if self.pending.len() < 4 {
+            return Ok(false);
+        }
+        
+        let id = self.pending[0];
+        if &self.pending[1..4] != b"CMD" {
+            return Err(Error::CommunicationFailed(format!("sim: invalid command header: {:02X?}", &self.pending[0..4])));
+        }
+        
+        let word = |index: usize| -> Option<u32> {
+            let offset = 4 + (index * 4);
+            self.pending.get(offset..(offset + 4)).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        };
+        
+        let (cmd, length) = match id {
+            0x20 => {
+                let (Some(addr), Some(bank_id_len)) = (word(0), word(1)) else { return Ok(false) };
+                (SimCommand::LoadFromPc { addr, bank_id_len }, 12 + (bank_id_len & 0x00FFFFFF) as usize)
+            },
+            0x30 => {
+                let (Some(addr), Some(bank_id_len)) = (word(0), word(1)) else { return Ok(false) };
+                (SimCommand::DumpToPc { addr, bank_id_len }, 12)
+            },
+            0x40 => {
+                let Some(header) = word(0) else { return Ok(false) };
+                let data_len = (header & 0x00FFFFFF) as usize;
+                let Some(data) = self.pending.get(8..(8 + data_len)) else { return Ok(false) };
+                
+                (SimCommand::TargetSideFifo((DataType::from((header >> 24) as u8), data.to_vec())), 8 + data_len)
+            },
+            0x70 | 0x72 | 0x74 => {
+                let Some(arg) = word(0) else { return Ok(false) };
+                (match id {
+                    0x70 => SimCommand::SetSaveType(arg),
+                    0x72 => SimCommand::SetCicType(arg),
+                    _ => SimCommand::SetCiExtended(arg),
+                }, 8)
+            },
+            0x80 => (SimCommand::VersionRequest, 4),
+            0x84 => (SimCommand::UpgradeStart, 4),
+            0x85 => (SimCommand::UpgradeReport, 4),
+            _ => return Err(Error::CommunicationFailed(format!("sim: unknown command id: {id:#04X}"))),
+        };
+        
+        if self.pending.len() < length {
+            return Ok(false);
+        }
+        let packet: Vec<u8> = self.pending.drain(..length).collect();
+        
+        match cmd {
+            SimCommand::LoadFromPc { addr, bank_id_len } => {
+                let bank_id = (bank_id_len >> 24) as u8;
+                // Bank 1 is the ROM. The newly uploaded game hasn't sent a heartbeat yet.
+                if bank_id == 1 {
+                    self.session.rom_uploaded();
+                }
+                let bank = self.banks.entry(bank_id).or_default();
+                let end = addr as usize + packet.len() - 12;
+                if bank.len() < end {
+                    bank.resize(end, 0x00);
+                }
+                bank[(addr as usize)..end].copy_from_slice(&packet[12..]);
+                
+                for &(_, corrupted) in self.corrupted.iter().filter(|(id, corrupted)| *id == bank_id && (addr as usize..end).contains(corrupted)) {
+                    bank[corrupted] ^= 0xFF;
+                }
+            },
+            SimCommand::DumpToPc { addr, bank_id_len } => {
+                let bank = self.banks.entry((bank_id_len >> 24) as u8).or_default();
+                let start = addr as usize;
+                let end = start + (bank_id_len & 0x00FFFFFF) as usize;
+                
+                let mut data = bank.get(start..end.min(bank.len())).unwrap_or_default().to_vec();
+                data.resize(end - start, 0x00);
+                self.rx.extend(data);
+            },
+            SimCommand::VersionRequest => {
+                let version = self.version;
+                self.rx.extend(version);
+            },
+            SimCommand::UpgradeStart => {
+                let result = self.upgrade_result.take().unwrap_or(UpgradeStatus::Success);
+                self.upgrade = VecDeque::from([UpgradeStatus::Verifying, UpgradeStatus::Erasing50, UpgradeStatus::Writing75, result]);
+            },
+            SimCommand::UpgradeReport => {
+                let status = match self.upgrade.len() {
+                    0 => UpgradeStatus::Ready,
+                    1 => self.upgrade[0],
+                    _ => self.upgrade.pop_front().unwrap(),
+                };
+                self.rx.extend([0, 0, 0, u8::from(status)]);
+            },
+            _ => (),
+        }
+        
+        self.rx.extend([0x43, 0x4D, 0x50, id]);
+        self.log.push(cmd);
+        
+        Ok(true)
+    }
+}
@@ -1,5 +1,10 @@
+use log::debug;
 use num_enum::{FromPrimitive, IntoPrimitive};
 
+pub mod binary;
+pub mod gdb;
+pub mod screenshot;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
 #[repr(u8)]
 pub enum DataType {
@@ -7,9 +12,110 @@ pub enum DataType {
     RawBinary = 0x02,
     Header = 0x03,
     Screenshot = 0x04,
+    /// Sent by newer USB libraries to announce their protocol version. See [`Heartbeat`].
+    Heartbeat = 0x05,
+    /// GDB remote serial protocol packet, without the `$` and checksum framing. See [`gdb`].
+    RdbPacket = 0x06,
+    
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+pub type DebugResponse = (DataType, Vec<u8>);
+
+/// Encodes the UNFLoader packet header word: the datatype in the upper 8 bits, and the data length
+/// in the lower 24 bits.
+pub fn encode_header(kind: DataType, length: usize) -> u32 {
+    ((u8::from(kind) as u32) << 24) | (length as u32 & 0x00FFFFFF)
+}
+
+/// Version of the UNFLoader USB protocol spoken by the game's USB library.
+/// 
+/// Libraries older than [`ProtocolVersion::V2`] don't send a [`Heartbeat`], so that version is
+/// assumed until one is received.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProtocolVersion(pub u16);
+impl Default for ProtocolVersion {
+    fn default() -> Self {
+        Self::V1
+    }
+}
+impl ProtocolVersion {
+    pub const V1: ProtocolVersion = ProtocolVersion(1);
+    pub const V2: ProtocolVersion = ProtocolVersion(2);
     
-    #[num_enum(default)]
-    Unknown,
+    /// Number of padding bytes between a 64drive packet's data and its "CMPH" marker. Since version
+    /// 2, the data is padded to a multiple of 4 bytes.
+    pub fn data_padding(&self, length: usize) -> usize {
+        if *self >= Self::V2 {
+            length.next_multiple_of(4) - length
+        } else {
+            0
+        }
+    }
 }
 
-pub type DebugResponse = (DataType, Vec<u8>);
\ No newline at end of file
+/// UNFLoader state of the game running on the cartridge. Every flashcart keeps one, and passes it
+/// the packets it receives and the ROMs it uploads, so the state is tracked the same way regardless
+/// of the cartridge.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Session {
+    protocol: ProtocolVersion,
+}
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Protocol version announced by the game's last heartbeat, or [`ProtocolVersion::V1`] if it
+    /// hasn't sent one.
+    pub fn protocol(&self) -> ProtocolVersion {
+        self.protocol
+    }
+    
+    /// Updates the state from a received packet.
+    pub fn receive(&mut self, kind: DataType, data: &[u8]) {
+        if kind != DataType::Heartbeat {
+            return;
+        }
+        
+        match Heartbeat::parse(data) {
+            Ok(heartbeat) if heartbeat.protocol != self.protocol => {
+                debug!("UNFLoader protocol version changed from {} to {}", self.protocol.0, heartbeat.protocol.0);
+                self.protocol = heartbeat.protocol;
+            },
+            Ok(_) => (),
+            Err(err) => debug!("Ignoring invalid heartbeat: {err}"),
+        }
+    }
+    
+    /// Forgets the previous game's state, since a new ROM hasn't announced its protocol version yet.
+    pub fn rom_uploaded(&mut self) {
+        if *self != Self::default() {
+            debug!("ROM uploaded, resetting the UNFLoader protocol version.");
+            *self = Self::default();
+        }
+    }
+}
+
+/// Contents of a [`DataType::Heartbeat`] packet, sent by the USB library when it starts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub protocol: ProtocolVersion,
+    /// Version of the heartbeat packet's own format.
+    pub version: u16,
+}
+impl Heartbeat {
+    /// Parses the heartbeat's data: the protocol version followed by the heartbeat version, as
+    /// big-endian 16-bit values.
+    pub fn parse(data: &[u8]) -> Result<Heartbeat, String> {
+        let Some(data) = data.get(0..4) else {
+            return Err(format!("heartbeat is too small: {} bytes (min 4)", data.len()));
+        };
+        
+        Ok(Heartbeat {
+            protocol: ProtocolVersion(u16::from_be_bytes([data[0], data[1]])),
+            version: u16::from_be_bytes([data[2], data[3]]),
+        })
+    }
+}
@@ -0,0 +1,50 @@
+//! Writes [`DataType::RawBinary`](crate::unfloader::DataType::RawBinary) packets to numbered files,
+//! such as profiling buffers or memory snapshots dumped by the running game.
+
+use std::io;
+use std::path::PathBuf;
+
+/// Writes each binary packet it's given to its own file, named `binary-0001.bin`, `binary-0002.bin`,
+/// and so on. Existing files are never overwritten; their numbers are skipped instead.
+#[derive(Clone, Debug)]
+pub struct BinaryWriter {
+    dir: PathBuf,
+    next: u32,
+}
+impl BinaryWriter {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            next: 1,
+        }
+    }
+    
+    /// Writes the data to the next free file, creating the output directory if needed.
+    pub fn write(&mut self, data: &[u8]) -> io::Result<SavedBinary> {
+        std::fs::create_dir_all(&self.dir)?;
+        
+        let path = loop {
+            let path = self.dir.join(format!("binary-{:04}.bin", self.next));
+            self.next += 1;
+            if !path.exists() {
+                break path;
+            }
+        };
+        std::fs::write(&path, data)?;
+        
+        Ok(SavedBinary {
+            path,
+            length: data.len(),
+            md5: format!("{:x}", md5::compute(data)),
+        })
+    }
+}
+
+/// Summary of a binary packet which was written to a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SavedBinary {
+    pub path: PathBuf,
+    pub length: usize,
+    /// MD5 hash of the data, as lowercase hex.
+    pub md5: String,
+}
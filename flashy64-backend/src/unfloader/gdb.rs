@@ -0,0 +1,112 @@
+//! Framing for GDB's remote serial protocol (RSP), as tunneled through UNFLoader.
+//! 
+//! GDB sends packets as `$<data>#<checksum>`, where the checksum is the sum of the data bytes modulo
+//! 256, as two hex digits. Over USB, only the data is sent, as a [`DataType::RdbPacket`] packet; the
+//! framing and acknowledgements (`+` and `-`) are handled on the PC side. GDB's interrupt request (a
+//! lone `0x03` byte outside of any packet) is forwarded as an RDB packet containing just that byte.
+//! 
+//! [`DataType::RdbPacket`]: crate::unfloader::DataType::RdbPacket
+
+/// Byte sent by GDB to interrupt the running program (CTRL+C).
+pub const INTERRUPT: u8 = 0x03;
+
+/// Something received from GDB.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GdbEvent {
+    /// A complete packet with a valid checksum. Contains the data between `$` and `#`.
+    Packet(Vec<u8>),
+    /// A packet whose checksum didn't match. GDB should be sent a `-` so it retransmits.
+    BadChecksum,
+    Interrupt,
+    /// GDB acknowledged the last packet.
+    Ack,
+    /// GDB rejected the last packet, which should be sent again.
+    Nack,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Idle,
+    Data,
+    Checksum,
+}
+
+/// Splits the byte stream received from GDB into [`GdbEvent`]s. Packets may be split across any
+/// number of reads.
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    state: State,
+    data: Vec<u8>,
+    checksum: Vec<u8>,
+}
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            state: State::Idle,
+            data: vec![],
+            checksum: vec![],
+        }
+    }
+    
+    /// Decodes the next bytes from the stream, returning any events they complete.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<GdbEvent> {
+        let mut events = vec![];
+        
+        for &byte in bytes {
+            match self.state {
+                State::Idle => match byte {
+                    b'$' => {
+                        self.data.clear();
+                        self.state = State::Data;
+                    },
+                    INTERRUPT => events.push(GdbEvent::Interrupt),
+                    b'+' => events.push(GdbEvent::Ack),
+                    b'-' => events.push(GdbEvent::Nack),
+                    _ => (),
+                },
+                State::Data => match byte {
+                    b'#' => {
+                        self.checksum.clear();
+                        self.state = State::Checksum;
+                    },
+                    _ => self.data.push(byte),
+                },
+                State::Checksum => {
+                    self.checksum.push(byte);
+                    if self.checksum.len() == 2 {
+                        let expected = std::str::from_utf8(&self.checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                        events.push(match expected {
+                            Some(expected) if expected == checksum(&self.data) => GdbEvent::Packet(std::mem::take(&mut self.data)),
+                            _ => GdbEvent::BadChecksum,
+                        });
+                        self.state = State::Idle;
+                    }
+                },
+            }
+        }
+        
+        events
+    }
+}
+
+
+
+/// Frames packet data to be sent to GDB, as `$<data>#<checksum>`.
+pub fn encode_packet(data: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(data.len() + 4);
+    packet.push(b'$');
+    packet.extend_from_slice(data);
+    packet.extend_from_slice(format!("#{:02x}", checksum(data)).as_bytes());
+    
+    packet
+}
+
+/// RSP checksum: the sum of all bytes, modulo 256.
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
@@ -0,0 +1,121 @@
+//! Decoder for framebuffers sent by UNFLoader's `usb_screenshot`.
+//! 
+//! A screenshot is sent as two packets. The first is a [`DataType::Header`] packet made up of four
+//! big-endian words:
+//! 
+//! | Offset | Field                                               |
+//! |--------|-----------------------------------------------------|
+//! | 0x00   | Datatype of the following packet (`Screenshot`)     |
+//! | 0x04   | Bytes per pixel: 2 for RGBA5551, or 4 for RGBA8888  |
+//! | 0x08   | Width in pixels                                     |
+//! | 0x0C   | Height in pixels                                    |
+//! 
+//! It's followed by a [`DataType::Screenshot`] packet containing the raw framebuffer.
+
+use std::io::Write;
+use crate::unfloader::DataType;
+
+pub const HEADER_LENGTH: usize = 16;
+/// Largest accepted width or height. The N64's video interface doesn't go beyond 640x576.
+pub const MAX_DIMENSION: u32 = 1024;
+
+/// Format of the framebuffer, taken from the preceding header packet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ScreenshotHeader {
+    /// Bytes per pixel, either 2 or 4.
+    pub depth: u32,
+    pub width: u32,
+    pub height: u32,
+}
+impl ScreenshotHeader {
+    /// Parses the data of a [`DataType::Header`] packet.
+    /// 
+    /// Fails if the header doesn't describe a screenshot, uses an unsupported pixel depth, or has a
+    /// width or height of 0 or more than [`MAX_DIMENSION`].
+    pub fn parse(data: &[u8]) -> Result<ScreenshotHeader, String> {
+        if data.len() < HEADER_LENGTH {
+            return Err(format!("header is too small: {} bytes (min {HEADER_LENGTH})", data.len()));
+        }
+        
+        let word = |offset: usize| u32::from_be_bytes(data[offset..(offset + 4)].try_into().unwrap());
+        let kind = DataType::from(word(0x00) as u8);
+        if kind != DataType::Screenshot {
+            return Err(format!("header describes {kind:?} data, not a screenshot"));
+        }
+        
+        let header = ScreenshotHeader {
+            depth: word(0x04),
+            width: word(0x08),
+            height: word(0x0C),
+        };
+        if header.depth != 2 && header.depth != 4 {
+            return Err(format!("unsupported pixel depth: {} bytes per pixel (expected 2 or 4)", header.depth));
+        }
+        if !(1..=MAX_DIMENSION).contains(&header.width) || !(1..=MAX_DIMENSION).contains(&header.height) {
+            return Err(format!("unsupported size: {}x{} (max {MAX_DIMENSION}x{MAX_DIMENSION})", header.width, header.height));
+        }
+        
+        Ok(header)
+    }
+    
+    /// Length of the framebuffer described by this header, in bytes, or `None` if it overflows.
+    pub fn frame_length(&self) -> Option<usize> {
+        (self.depth as usize).checked_mul(self.width as usize)?.checked_mul(self.height as usize)
+    }
+}
+
+/// A decoded screenshot, stored as 8-bit RGB.
+/// 
+/// The framebuffer's alpha bits hold the RDP's coverage values rather than transparency, so they're
+/// discarded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Screenshot {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+impl Screenshot {
+    /// Decodes the data of a [`DataType::Screenshot`] packet, using the format from its header.
+    pub fn decode(header: &ScreenshotHeader, data: &[u8]) -> Result<Screenshot, String> {
+        let length = header.frame_length()
+            .ok_or_else(|| format!("framebuffer size overflows: {}x{} at {} bytes per pixel", header.width, header.height, header.depth))?;
+        if data.len() < length {
+            return Err(format!("framebuffer is too small: {} bytes vs expected {length} for {}x{} at {} bytes per pixel",
+                data.len(), header.width, header.height, header.depth));
+        }
+        
+        let data = &data[..length];
+        let pixels = match header.depth {
+            2 => data.chunks_exact(2).flat_map(|pixel| {
+                let pixel = u16::from_be_bytes([pixel[0], pixel[1]]);
+                [expand_5bit(pixel >> 11), expand_5bit(pixel >> 6), expand_5bit(pixel >> 1)]
+            }).collect(),
+            _ => data.chunks_exact(4).flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect(),
+        };
+        
+        Ok(Screenshot {
+            width: header.width,
+            height: header.height,
+            pixels,
+        })
+    }
+    
+    /// Encodes the screenshot as a PNG image.
+    pub fn write_png<W: Write>(&self, writer: W) -> Result<(), String> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        
+        let mut writer = encoder.write_header().map_err(|err| err.to_string())?;
+        writer.write_image_data(&self.pixels).map_err(|err| err.to_string())
+    }
+}
+
+
+
+/// Scales the lower 5 bits of a color channel up to 8 bits, so that full intensity stays at 0xFF.
+fn expand_5bit(value: u16) -> u8 {
+    let value = (value & 0x1F) as u8;
+    
+    (value << 3) | (value >> 2)
+}
@@ -0,0 +1,64 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+use flashy64_backend::{Error, Result};
+use flashy64_backend::transport::{Identity, Transport};
+
+/// Transport which replies with scripted data, and records everything written to it. Clones share
+/// the same state, so a test can keep one while the cartridge owns the other.
+#[derive(Clone, Debug, Default)]
+pub struct MockTransport {
+    replies: Rc<RefCell<VecDeque<u8>>>,
+    written: Rc<RefCell<Vec<u8>>>,
+}
+impl Transport for MockTransport {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        let mut replies = self.replies.borrow_mut();
+        if replies.len() < buf.len() {
+            return Err(Error::Timeout);
+        }
+        
+        let length = buf.len();
+        for (dst, src) in buf.iter_mut().zip(replies.drain(..length)) {
+            *dst = src;
+        }
+        Ok(())
+    }
+    
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        self.written.borrow_mut().extend_from_slice(data);
+        Ok(())
+    }
+    
+    fn bytes_available(&mut self) -> Result<usize> {
+        Ok(self.replies.borrow().len())
+    }
+    
+    fn purge(&mut self) -> Result<()> {
+        Ok(())
+    }
+    
+    fn set_timeouts(&mut self, _read: Duration, _write: Duration) -> Result<()> {
+        Ok(())
+    }
+    
+    fn identity(&mut self) -> Result<Identity> {
+        Ok(Identity::default())
+    }
+}
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Queues data to be read by the cartridge.
+    pub fn reply(&self, data: &[u8]) {
+        self.replies.borrow_mut().extend(data);
+    }
+    
+    /// Removes and returns everything written since the last call.
+    pub fn take_written(&self) -> Vec<u8> {
+        self.written.take()
+    }
+}
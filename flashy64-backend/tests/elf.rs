@@ -0,0 +1,181 @@
+use gimli::{Encoding, Format, LineEncoding};
+use gimli::write::{Address, AttributeValue, DwarfUnit, EndianVec, LineProgram, LineString, Sections};
+use object::{Architecture, BinaryFormat, Endianness, SectionKind, SymbolFlags, SymbolKind, SymbolScope};
+use object::write::{Object, Symbol, SymbolSection};
+use flashy64_backend::carts::Cic;
+use flashy64_backend::elf::{build_rom, IPL3_LENGTH, Symbolizer};
+use flashy64_backend::rom;
+use flashy64_backend::rom::RomHeader;
+
+/// Builds a MIPS ELF with two functions, `main` at 0x80000400 (0x40 bytes) and `crash` at 0x80000440
+/// (size unknown). Line info is included if `lines` is set: main.c line 10 for main's first 0x10 bytes,
+/// line 12 for the rest, and crash.c line 3 for crash.
+fn elf(lines: bool) -> Vec<u8> {
+    let mut obj = Object::new(BinaryFormat::Elf, Architecture::Mips, Endianness::Big);
+    let text = obj.add_section(vec![], b".text".to_vec(), SectionKind::Text);
+    obj.append_section_data(text, &[0; 0x80], 4);
+    
+    for (name, value, size) in [("main", 0x80000400, 0x40), ("crash", 0x80000440, 0)] {
+        obj.add_symbol(Symbol {
+            name: name.as_bytes().to_vec(),
+            value,
+            size,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Linkage,
+            weak: false,
+            section: SymbolSection::Section(text),
+            flags: SymbolFlags::None,
+        });
+    }
+    
+    if lines {
+        let encoding = Encoding { format: Format::Dwarf32, version: 4, address_size: 4 };
+        let mut dwarf = DwarfUnit::new(encoding);
+        let mut program = LineProgram::new(encoding, LineEncoding::default(), LineString::String(b"/src".to_vec()), LineString::String(b"main.c".to_vec()), None);
+        let dir = program.default_directory();
+        
+        for (file, address, rows) in [("main.c", 0x80000400, vec![(0x00, 10), (0x10, 12)]), ("crash.c", 0x80000440, vec![(0x00, 3)])] {
+            let file = program.add_file(LineString::String(file.as_bytes().to_vec()), dir, None);
+            program.begin_sequence(Some(Address::Constant(address)));
+            for (offset, line) in rows {
+                program.row().address_offset = offset;
+                program.row().file = file;
+                program.row().line = line;
+                program.generate_row();
+            }
+            program.end_sequence(0x40);
+        }
+        dwarf.unit.line_program = program;
+        
+        let root = dwarf.unit.root();
+        dwarf.unit.get_mut(root).set(gimli::DW_AT_low_pc, AttributeValue::Address(Address::Constant(0x80000400)));
+        dwarf.unit.get_mut(root).set(gimli::DW_AT_high_pc, AttributeValue::Udata(0x80));
+        
+        let mut sections = Sections::new(EndianVec::new(gimli::BigEndian));
+        dwarf.write(&mut sections).unwrap();
+        sections.for_each(|id, data| {
+            if !data.slice().is_empty() {
+                let section = obj.add_section(vec![], id.name().as_bytes().to_vec(), SectionKind::Debug);
+                obj.append_section_data(section, data.slice(), 1);
+            }
+            Ok::<(), gimli::write::Error>(())
+        }).unwrap();
+    }
+    
+    obj.write().unwrap()
+}
+
+/// Builds a big-endian MIPS ELF32 executable with one PT_LOAD segment per `(address, data, memory size)`.
+/// object's writer doesn't emit program headers, so this is done by hand.
+fn executable(entry: u32, segments: &[(u32, &[u8], u32)]) -> Vec<u8> {
+    let mut elf = vec![0x7F, b'E', b'L', b'F', 1, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    for half in [2u16, 8] {
+        elf.extend_from_slice(&half.to_be_bytes());
+    }
+    for word in [1, entry, 52, 0, 0] {
+        elf.extend_from_slice(&word.to_be_bytes());
+    }
+    for half in [52u16, 32, segments.len() as u16, 40, 0, 0] {
+        elf.extend_from_slice(&half.to_be_bytes());
+    }
+    
+    let mut offset = 52 + 32 * segments.len() as u32;
+    for &(address, data, memory_size) in segments {
+        for word in [1, offset, address, address, data.len() as u32, memory_size, 5, 4] {
+            elf.extend_from_slice(&word.to_be_bytes());
+        }
+        offset += data.len() as u32;
+    }
+    for (_, data, _) in segments {
+        elf.extend_from_slice(data);
+    }
+    
+    elf
+}
+
+#[test]
+fn functions_are_found_by_address() {
+    let symbols = Symbolizer::parse(&elf(false)).unwrap();
+    
+    assert_eq!(symbols.symbols().len(), 2);
+    assert_eq!(symbols.function(0x80000400).map(|(symbol, offset)| (symbol.name.as_str(), offset)), Some(("main", 0)));
+    assert_eq!(symbols.function(0x8000043C).map(|(symbol, offset)| (symbol.name.as_str(), offset)), Some(("main", 0x3C)));
+    assert_eq!(symbols.function(0x80001000).map(|(symbol, offset)| (symbol.name.as_str(), offset)), Some(("crash", 0xBC0)));
+    assert!(symbols.function(0x800003FC).is_none());
+    assert_eq!(symbols.describe(0x80000410), Some("main+0x10".into()));
+}
+
+#[test]
+fn line_info_is_used_when_present() {
+    let symbols = Symbolizer::parse(&elf(true)).unwrap();
+    
+    assert_eq!(symbols.location(0x80000404), Some(("main.c".into(), 10)));
+    assert_eq!(symbols.describe(0x80000418), Some("main+0x18 at main.c:12".into()));
+    assert_eq!(symbols.describe(0x80000444), Some("crash+0x4 at crash.c:3".into()));
+}
+
+#[test]
+fn addresses_in_text_are_annotated() {
+    let symbols = Symbolizer::parse(&elf(false)).unwrap();
+    
+    assert_eq!(symbols.annotate("EPC: 0x80000408, RA: 80000440\n"), "EPC: 0x80000408 <main+0x8>, RA: 80000440 <crash>\n");
+    assert_eq!(symbols.annotate("sp ffffffff80000420"), "sp ffffffff80000420 <main+0x20>");
+    assert_eq!(symbols.annotate("0x800003FC 0x00000400 x80000400 800004000"), "0x800003FC 0x00000400 x80000400 800004000");
+}
+
+#[test]
+fn invalid_elf_is_rejected() {
+    assert!(Symbolizer::parse(b"not an elf").is_err());
+}
+
+#[test]
+fn segments_are_laid_out_behind_the_ipl3() {
+    let elf = executable(0x80000400, &[(0x80000400, &[0x11; 0x10], 0x10), (0x80000420, &[0x22; 4], 0x100), (0x80000600, &[], 0x40)]);
+    let data = build_rom(&elf, &[0; IPL3_LENGTH], Cic::Auto, "homebrew").unwrap();
+    let header = RomHeader::parse(&data).unwrap();
+    
+    assert_eq!(data.len(), 0x101000);
+    assert_eq!(header.name, "homebrew");
+    assert_eq!(header.boot_address, 0x80000400);
+    assert_eq!(&data[0x1000..0x1010], &[0x11; 0x10]);
+    assert_eq!(&data[0x1010..0x1020], &[0x00; 0x10]);
+    assert_eq!(&data[0x1020..0x1024], &[0x22; 4]);
+    assert!(data[0x1024..].iter().all(|&byte| byte == 0x00));
+}
+
+#[test]
+fn boot_address_and_checksum_follow_the_cic() {
+    let mut template = vec![0; 0x1000];
+    template[0x00..0x04].copy_from_slice(&rom::HEADER_MAGIC);
+    template[0x20..0x34].copy_from_slice(b"TEMPLATE            ");
+    let elf = executable(0x80000400, &[(0x80000400, &[0x33; 0x100], 0x100)]);
+    
+    let data = build_rom(&elf, &template, Cic::VarX106, "homebrew").unwrap();
+    let header = RomHeader::parse(&data).unwrap();
+    assert_eq!(header.name, "TEMPLATE");
+    assert_eq!(header.boot_address, 0x7FE00400);
+    assert_eq!((header.crc1, header.crc2), rom::checksum(&data, Cic::VarX106).unwrap());
+    
+    let data = build_rom(&elf, &template, Cic::Var6102, "homebrew").unwrap();
+    let header = RomHeader::parse(&data).unwrap();
+    assert_eq!(header.boot_address, 0x80000400);
+    assert_eq!((header.crc1, header.crc2), rom::checksum(&data, Cic::Var6102).unwrap());
+}
+
+#[test]
+fn unbootable_elfs_are_rejected() {
+    let ipl3 = [0; IPL3_LENGTH];
+    
+    assert!(build_rom(&executable(0x80000410, &[(0x80000400, &[0; 0x20], 0x20)]), &ipl3, Cic::Auto, "").is_err());
+    assert!(build_rom(&executable(0x80000400, &[]), &ipl3, Cic::Auto, "").is_err());
+    assert!(build_rom(&executable(0x80000400, &[(0x80000400, &[0; 0x20], 0x20)]), &ipl3[..0x100], Cic::Auto, "").is_err());
+    assert!(build_rom(b"not an elf", &ipl3, Cic::Auto, "").is_err());
+}
+
+#[test]
+fn segments_beyond_the_largest_rom_are_rejected() {
+    let elf = executable(0x80000400, &[(0x80000400, &[0; 0x20], 0x20), (0xA0000000, &[0; 0x20], 0x20)]);
+    let err = build_rom(&elf, &[0; IPL3_LENGTH], Cic::Auto, "").unwrap_err();
+    
+    assert!(err.contains("0xA0000000"), "error should name the segment: {err}");
+}
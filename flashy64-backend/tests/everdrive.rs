@@ -0,0 +1,100 @@
+mod common;
+
+use flashy64_backend::{Error, Flashcart};
+use flashy64_backend::carts::SaveType;
+use flashy64_backend::carts::everdrive::{Command, EverDrive, ROM_ADDRESS, ROM_FILL_LENGTH};
+use flashy64_backend::unfloader::DataType;
+use common::MockTransport;
+
+fn packet(id: u8, addr: u32, blocks: u32) -> Vec<u8> {
+    let mut data = vec![b'c', b'm', b'd', id];
+    data.extend(addr.to_be_bytes());
+    data.extend(blocks.to_be_bytes());
+    data.extend([0; 4]);
+    
+    data
+}
+
+fn test_response() -> Vec<u8> {
+    let mut data = b"cmdr".to_vec();
+    data.resize(16, 0x00);
+    
+    data
+}
+
+fn cart() -> (MockTransport, EverDrive<MockTransport>) {
+    let mock = MockTransport::new();
+    mock.reply(&test_response());
+    let cart = EverDrive::new(mock.clone()).unwrap();
+    
+    assert_eq!(mock.take_written(), packet(b't', 0, 0));
+    (mock, cart)
+}
+
+#[test]
+fn command_lengths_are_encoded_in_blocks() {
+    assert_eq!(Command::RomWrite { addr: ROM_ADDRESS, length: 0x400 }.encode_packet(), packet(b'W', ROM_ADDRESS, 2));
+    assert_eq!(Command::RomRead { addr: 0x08000000, length: 0x8000 }.encode_packet(), packet(b'R', 0x08000000, 0x40));
+    assert_eq!(Command::Start.encode_packet(), packet(b's', 0, 0));
+}
+
+#[test]
+fn uploads_are_padded_to_512_byte_blocks() {
+    let (mock, mut cart) = cart();
+    
+    cart.upload_rom_at(0x1000, &[0xAA; 100], None).unwrap();
+    assert_eq!(mock.take_written(), [packet(b'W', ROM_ADDRESS + 0x1000, 1), vec![0xAA; 100], vec![0x00; 412]].concat());
+}
+
+#[test]
+fn small_roms_are_zero_filled_first() {
+    let (mock, mut cart) = cart();
+    mock.reply(&test_response());
+    
+    cart.upload_rom(&[0xAA; 0x200], None).unwrap();
+    assert_eq!(mock.take_written(), [
+        packet(b'c', ROM_ADDRESS, ROM_FILL_LENGTH / 512),
+        packet(b't', 0, 0),
+        packet(b'W', ROM_ADDRESS, 1),
+        vec![0xAA; 0x200],
+    ].concat());
+}
+
+#[test]
+fn debug_frames_are_padded_to_512_bytes() {
+    let (mock, mut cart) = cart();
+    
+    cart.send_debug(DataType::Text, b"hello").unwrap();
+    let frame = mock.take_written();
+    assert_eq!(frame.len(), 512);
+    assert_eq!(&frame[0..4], b"DMA@");
+    assert_eq!(&frame[4..8], &[0x01, 0x00, 0x00, 0x05]);
+    assert_eq!(&frame[8..17], b"helloCMPH");
+    assert!(frame[17..].iter().all(|&byte| byte == 0x00));
+}
+
+#[test]
+fn received_debug_frames_are_unpadded() {
+    let (mock, mut cart) = cart();
+    let mut frame = b"DMA@\x01\x00\x00\x05helloCMPH".to_vec();
+    frame.resize(32, 0x00);
+    mock.reply(&frame);
+    
+    assert_eq!(cart.poll_debug().unwrap(), Some((DataType::Text, b"hello".to_vec())));
+    assert_eq!(cart.poll_debug().unwrap(), None);
+}
+
+#[test]
+fn savetype_is_patched_into_the_rom_header() {
+    let (mock, mut cart) = cart();
+    let header: Vec<u8> = (0..512).map(|i| i as u8).collect();
+    mock.reply(&header);
+    
+    cart.set_savetype(SaveType::Sram256Kbit).unwrap();
+    let mut patched = header.clone();
+    patched[0x3C..0x40].copy_from_slice(&[b'E', b'D', header[0x3E], 0x30]);
+    assert_eq!(mock.take_written(), [packet(b'R', ROM_ADDRESS, 1), packet(b'W', ROM_ADDRESS, 1), patched].concat());
+    
+    assert!(matches!(cart.set_savetype(SaveType::Auto), Err(Error::Unsupported)));
+    assert!(mock.take_written().is_empty());
+}
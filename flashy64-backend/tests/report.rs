@@ -0,0 +1,96 @@
+use std::time::Duration;
+use flashy64_backend::report::{Report, ReportFormat, TestCase, TestStatus};
+
+fn report() -> Report {
+    let mut report = Report::new();
+    report.feed("Booting...\nTEST:math:sqrt:PASS:0.25\nTEST:math:div:FA");
+    report.feed("IL:12:expected <1> & got \"2\"\nTEST:audio:mixer:SKIP\n");
+    report.feed("TEST:audio:unfinished:PASS");
+    
+    report
+}
+
+#[test]
+fn result_lines_are_parsed() {
+    assert_eq!(TestCase::parse("TEST:gfx:fill:PASS:1.5:took a while: 3 frames\r\n"), Some(TestCase {
+        suite: "gfx".into(),
+        name: "fill".into(),
+        status: TestStatus::Passed,
+        duration: Duration::from_micros(1500),
+        message: Some("took a while: 3 frames".into()),
+    }));
+    assert_eq!(TestCase::parse("TEST:gfx:fill:skip").unwrap().status, TestStatus::Skipped);
+    
+    assert_eq!(TestCase::parse("gfx:fill:PASS"), None);
+    assert_eq!(TestCase::parse("TEST:gfx:fill:MAYBE"), None);
+    assert_eq!(TestCase::parse("TEST:gfx"), None);
+}
+
+#[test]
+fn invalid_duration_keeps_the_result() {
+    let case = TestCase::parse("TEST:gfx:fill:FAIL:soon:wrong colour").unwrap();
+    
+    assert_eq!(case.status, TestStatus::Failed);
+    assert_eq!(case.duration, Duration::ZERO);
+    assert_eq!(case.message.as_deref(), Some("wrong colour"));
+    assert_eq!(TestCase::parse("TEST:gfx:fill:PASS:-1").unwrap().duration, Duration::ZERO);
+}
+
+#[test]
+fn lines_split_across_packets_are_collected() {
+    let report = report();
+    
+    assert_eq!(report.cases.len(), 3, "the unterminated line shouldn't be counted yet");
+    assert_eq!(report.cases[1].status, TestStatus::Failed);
+    assert_eq!(report.cases[1].message.as_deref(), Some("expected <1> & got \"2\""));
+    assert_eq!((report.count(TestStatus::Passed), report.count(TestStatus::Failed), report.count(TestStatus::Skipped)), (1, 1, 1));
+}
+
+#[test]
+fn unterminated_line_is_parsed_when_finished() {
+    let mut report = report();
+    report.finish();
+    
+    assert_eq!(report.cases.len(), 4);
+    assert_eq!(report.cases[3].name, "unfinished");
+    assert_eq!(report.cases[3].status, TestStatus::Passed);
+    
+    report.finish();
+    assert_eq!(report.cases.len(), 4, "finishing again shouldn't add the line twice");
+}
+
+#[test]
+fn junit_report_groups_suites() {
+    assert_eq!(report().write(ReportFormat::JUnit), r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites tests="3" failures="1" skipped="1" time="0.012">
+  <testsuite name="math" tests="2" failures="1" skipped="0" time="0.012">
+    <testcase name="sqrt" classname="math" time="0.000"/>
+    <testcase name="div" classname="math" time="0.012">
+      <failure message="expected &lt;1&gt; &amp; got &quot;2&quot;"/>
+    </testcase>
+  </testsuite>
+  <testsuite name="audio" tests="1" failures="0" skipped="1" time="0.000">
+    <testcase name="mixer" classname="audio" time="0.000">
+      <skipped message=""/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#);
+}
+
+#[test]
+fn json_report_lists_cases() {
+    assert_eq!(report().write(ReportFormat::Json), r#"{
+  "tests": 3,
+  "passed": 1,
+  "failed": 1,
+  "skipped": 1,
+  "cases": [
+    {"suite": "math", "name": "sqrt", "status": "pass", "duration_ms": 0.25, "message": null},
+    {"suite": "math", "name": "div", "status": "fail", "duration_ms": 12, "message": "expected <1> & got \"2\""},
+    {"suite": "audio", "name": "mixer", "status": "skip", "duration_ms": 0, "message": null}
+  ]
+}
+"#);
+    assert_eq!(Report::new().to_json(), "{\n  \"tests\": 0,\n  \"passed\": 0,\n  \"failed\": 0,\n  \"skipped\": 0,\n  \"cases\": []\n}\n");
+}
@@ -0,0 +1,142 @@
+use flashy64_backend::carts::{Cic, SaveType};
+use flashy64_backend::rom;
+use flashy64_backend::rom::{ByteOrder, RomHeader};
+
+fn z64() -> Vec<u8> {
+    let mut data = vec![0x80, 0x37, 0x12, 0x40];
+    data.extend((4..0x1000u32).map(|i| (i * 7) as u8));
+    
+    data
+}
+
+fn header() -> Vec<u8> {
+    let mut data = z64();
+    data[0x04..0x18].copy_from_slice(&[
+        0x00, 0x00, 0x00, 0x0F,
+        0x80, 0x24, 0x60, 0x00,
+        0x00, 0x00, 0x14, 0x44,
+        0x63, 0x5A, 0x2B, 0xFF,
+        0x8B, 0x02, 0x23, 0x26,
+    ]);
+    data[0x20..0x34].copy_from_slice(b"SUPER MARIO 64      ");
+    data[0x3B..0x40].copy_from_slice(b"NSME\x00");
+    
+    data
+}
+
+#[test]
+fn byte_order_is_detected() {
+    assert_eq!(ByteOrder::detect(&[0x80, 0x37, 0x12, 0x40]), Some(ByteOrder::BigEndian));
+    assert_eq!(ByteOrder::detect(&[0x37, 0x80, 0x40, 0x12]), Some(ByteOrder::ByteSwapped));
+    assert_eq!(ByteOrder::detect(&[0x40, 0x12, 0x37, 0x80]), Some(ByteOrder::LittleEndian));
+    assert_eq!(ByteOrder::detect(&[0x12, 0x34, 0x56, 0x78]), None);
+    assert_eq!(ByteOrder::detect(&[0x80, 0x37]), None);
+}
+
+#[test]
+fn conversions_round_trip() {
+    let data = z64();
+    
+    for from in [ByteOrder::BigEndian, ByteOrder::ByteSwapped, ByteOrder::LittleEndian] {
+        let converted = rom::convert(&data, ByteOrder::BigEndian, from);
+        assert_eq!(ByteOrder::detect(&converted), Some(from));
+        
+        for to in [ByteOrder::BigEndian, ByteOrder::ByteSwapped, ByteOrder::LittleEndian] {
+            let reconverted = rom::convert(&converted, from, to);
+            assert_eq!(rom::convert(&reconverted, to, ByteOrder::BigEndian), data, "{from:?} -> {to:?}");
+        }
+        
+        assert_eq!(rom::normalize(&converted).as_ref(), data.as_slice());
+    }
+}
+
+#[test]
+fn conversion_keeps_trailing_bytes() {
+    assert_eq!(rom::convert(&[1, 2, 3, 4, 5, 6], ByteOrder::BigEndian, ByteOrder::LittleEndian), vec![4, 3, 2, 1, 5, 6]);
+    assert_eq!(rom::convert(&[1, 2, 3, 4, 5, 6], ByteOrder::BigEndian, ByteOrder::ByteSwapped), vec![2, 1, 4, 3, 5, 6]);
+}
+
+#[test]
+fn unknown_data_is_not_normalized() {
+    let data = vec![0x12, 0x34, 0x56, 0x78, 0x9A];
+    
+    assert_eq!(rom::normalize(&data).as_ref(), data.as_slice());
+}
+
+#[test]
+fn savetype_detection_ignores_byte_order() {
+    let data = z64();
+    let savetype = SaveType::from_rom(&data);
+    
+    assert_eq!(SaveType::from_rom(&rom::convert(&data, ByteOrder::BigEndian, ByteOrder::ByteSwapped)), savetype);
+    assert_eq!(SaveType::from_rom(&rom::convert(&data, ByteOrder::BigEndian, ByteOrder::LittleEndian)), savetype);
+}
+
+#[test]
+fn header_is_parsed() {
+    let header = RomHeader::parse(&header()).unwrap();
+    
+    assert_eq!(header, RomHeader {
+        pi_config: 0x80371240,
+        clock_rate: 0x0000000F,
+        boot_address: 0x80246000,
+        libultra_version: 0x00001444,
+        crc1: 0x635A2BFF,
+        crc2: 0x8B022326,
+        name: "SUPER MARIO 64".into(),
+        media_format: b'N',
+        cartridge_id: *b"SM",
+        region: b'E',
+        revision: 0,
+    });
+    assert_eq!(header.game_id(), "NSME");
+    assert_eq!(header.media_name(), Some("Cartridge"));
+    assert_eq!(header.region_name(), Some("North America"));
+}
+
+#[test]
+fn header_is_parsed_in_any_byte_order() {
+    let data = header();
+    let expected = RomHeader::parse(&data).unwrap();
+    
+    assert_eq!(RomHeader::parse(&rom::convert(&data, ByteOrder::BigEndian, ByteOrder::ByteSwapped)), Ok(expected.clone()));
+    assert_eq!(RomHeader::parse(&rom::convert(&data, ByteOrder::BigEndian, ByteOrder::LittleEndian)), Ok(expected));
+}
+
+#[test]
+fn invalid_headers_are_rejected() {
+    assert!(RomHeader::parse(&header()[0..0x3F]).is_err());
+    
+    let mut data = header();
+    data[0] = 0x00;
+    assert!(RomHeader::parse(&data).is_err());
+}
+
+#[test]
+fn checksum_matches_reference() {
+    let data: Vec<u8> = (0..0x101000usize).map(|i| ((i * 7) ^ (i >> 8) ^ (i >> 16)) as u8).collect();
+    
+    assert_eq!(rom::checksum(&data, Cic::Var6102), Some((0xF8CE4DDC, 0x33784CD7)));
+    assert_eq!(rom::checksum(&data, Cic::VarX103), Some((0xA3946759, 0xEF6153E2)));
+    assert_eq!(rom::checksum(&data, Cic::VarX105), Some((0xDF2AF436, 0xD15EFCCA)));
+    assert_eq!(rom::checksum(&data, Cic::VarX106), Some((0x04100F9E, 0xDB7EE1F6)));
+    assert_eq!(rom::checksum(&data, Cic::Unknown), None);
+}
+
+#[test]
+fn checksum_pads_small_roms() {
+    assert_eq!(rom::checksum(&[0; 0x2000], Cic::Var6101), Some((0xF8CA4DDC, 0x303A4DDC)));
+    assert_eq!(rom::checksum(&header(), Cic::Var6102), rom::checksum(&rom::convert(&header(), ByteOrder::BigEndian, ByteOrder::LittleEndian), Cic::Var6102));
+}
+
+#[test]
+fn checksum_is_written_to_header() {
+    let mut data = header();
+    let checksum = rom::checksum(&data, Cic::VarX105).unwrap();
+    
+    rom::write_checksum(&mut data, checksum);
+    
+    let header = RomHeader::parse(&data).unwrap();
+    assert_eq!((header.crc1, header.crc2), checksum);
+    assert_eq!(rom::checksum(&data, Cic::VarX105), Some(checksum), "header isn't part of the checksum");
+}
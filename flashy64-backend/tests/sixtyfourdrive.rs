@@ -0,0 +1,401 @@
+mod common;
+
+use common::MockTransport;
+use flashy64_backend::{Capabilities, CartInfo, Error, Flashcart};
+use flashy64_backend::carts::{Cic, CRC, SaveType};
+use flashy64_backend::carts::sixtyfourdrive::{Model, Segment, SixtyFourDrive};
+use flashy64_backend::carts::sixtyfourdrive::rpk;
+use flashy64_backend::carts::sixtyfourdrive::rpk::KnownFirmware;
+use flashy64_backend::transport::sim::{SimCommand, SimSixtyFourDrive};
+use flashy64_backend::progress::TransferProgress;
+use flashy64_backend::unfloader::{DataType, ProtocolVersion};
+
+fn cart(model: Model) -> (SimSixtyFourDrive, SixtyFourDrive<SimSixtyFourDrive>) {
+    let sim = SimSixtyFourDrive::new(model);
+    let cart = SixtyFourDrive::new(sim.clone()).unwrap();
+    sim.clear_log();
+    
+    (sim, cart)
+}
+
+fn pattern(length: usize) -> Vec<u8> {
+    (0..length).map(|i| (i ^ (i >> 8) ^ (i >> 16)) as u8).collect()
+}
+
+#[test]
+fn upload_is_split_into_8mib_chunks() {
+    let (sim, mut cart) = cart(Model::HW2);
+    let rom = pattern(0x1400000);
+    
+    cart.upload_rom(&rom, None).unwrap();
+    
+    assert_eq!(sim.log(), vec![
+        SimCommand::LoadFromPc { addr: 0x000000, bank_id_len: 0x01800000 },
+        SimCommand::LoadFromPc { addr: 0x800000, bank_id_len: 0x01800000 },
+        SimCommand::LoadFromPc { addr: 0x1000000, bank_id_len: 0x01400000 },
+    ]);
+    assert_eq!(sim.bank(1), rom);
+}
+
+#[test]
+fn download_is_split_into_128kib_chunks() {
+    let (sim, mut cart) = cart(Model::HW2);
+    let rom = pattern(0x30000);
+    sim.set_bank(1, rom.clone());
+    
+    assert_eq!(cart.download_rom(0x30000, None).unwrap(), rom);
+    assert_eq!(sim.log(), vec![
+        SimCommand::DumpToPc { addr: 0x00000, bank_id_len: 0x01020000 },
+        SimCommand::DumpToPc { addr: 0x20000, bank_id_len: 0x01010000 },
+    ]);
+}
+
+#[test]
+fn download_length_is_rounded_up_to_word() {
+    let (sim, mut cart) = cart(Model::HW2);
+    sim.set_bank(1, pattern(16));
+    
+    assert_eq!(cart.download_rom(5, None).unwrap(), pattern(8));
+    assert_eq!(cart.download_rom(0, None).unwrap(), Vec::<u8>::new());
+}
+
+#[test]
+fn segments_use_their_own_banks() {
+    let (sim, mut cart) = cart(Model::HW2);
+    
+    for (segment, bank) in [(Segment::Sram256, 2), (Segment::Sram768, 3), (Segment::FlashRam, 4), (Segment::Eeprom16, 6)] {
+        let data = vec![bank; 64];
+        cart.upload(segment, 0, &data, None).unwrap();
+        
+        assert_eq!(sim.bank(bank), data);
+        assert_eq!(cart.download(segment, 0, 64, None).unwrap(), data);
+    }
+}
+
+#[test]
+fn download_is_limited_to_segment_length() {
+    let (sim, mut cart) = cart(Model::HW2);
+    sim.set_bank(6, pattern(0x1000));
+    
+    assert_eq!(cart.download(Segment::Eeprom4, 0, 0x1000, None).unwrap(), pattern(512));
+}
+
+#[test]
+fn cic_and_savetype_are_encoded() {
+    let (sim, mut cart) = cart(Model::HW2);
+    
+    cart.set_cic(Cic::VarX105).unwrap();
+    cart.set_savetype(SaveType::FlashRam1Mbit).unwrap();
+    
+    assert_eq!(sim.log(), vec![
+        SimCommand::SetCicType(0x80000005),
+        SimCommand::SetSaveType(4),
+    ]);
+}
+
+#[test]
+fn recv_debug_parses_frames() {
+    let (sim, mut cart) = cart(Model::HW2);
+    sim.inject_debug(DataType::Text, b"Hello, N64!");
+    sim.inject_debug(DataType::RawBinary, &[0xDE, 0xAD, 0xBE, 0xEF]);
+    
+    assert_eq!(cart.recv_debug().unwrap(), (DataType::Text, b"Hello, N64!".to_vec()));
+    assert_eq!(cart.poll_debug().unwrap(), Some((DataType::RawBinary, vec![0xDE, 0xAD, 0xBE, 0xEF])));
+    assert_eq!(cart.poll_debug().unwrap(), None);
+    assert_eq!(cart.recv_debug(), Err(Error::Timeout));
+}
+
+#[test]
+fn heartbeat_switches_protocol_version() {
+    let (sim, mut cart) = cart(Model::HW2);
+    assert_eq!(cart.protocol_version(), ProtocolVersion::V1);
+    
+    sim.inject_debug(DataType::Heartbeat, &[0x00, 0x02, 0x00, 0x01]);
+    sim.inject_debug(DataType::Text, b"Hello");
+    sim.inject_debug(DataType::Text, b"Hello!!!");
+    
+    assert_eq!(cart.recv_debug().unwrap(), (DataType::Heartbeat, vec![0x00, 0x02, 0x00, 0x01]));
+    assert_eq!(cart.protocol_version(), ProtocolVersion::V2);
+    assert_eq!(cart.recv_debug().unwrap(), (DataType::Text, b"Hello".to_vec()));
+    assert_eq!(cart.recv_debug().unwrap(), (DataType::Text, b"Hello!!!".to_vec()));
+}
+
+#[test]
+fn uploading_a_rom_resets_protocol_version() {
+    let (sim, mut cart) = cart(Model::HW2);
+    sim.inject_debug(DataType::Heartbeat, &[0x00, 0x02, 0x00, 0x01]);
+    cart.recv_debug().unwrap();
+    assert_eq!(cart.protocol_version(), ProtocolVersion::V2);
+    
+    cart.upload_rom(&pattern(0x1000), None).unwrap();
+    assert_eq!(cart.protocol_version(), ProtocolVersion::V1);
+    
+    sim.inject_debug(DataType::Text, b"Hello");
+    sim.inject_debug(DataType::Text, b"Hello!!!");
+    assert_eq!(cart.recv_debug().unwrap(), (DataType::Text, b"Hello".to_vec()));
+    assert_eq!(cart.recv_debug().unwrap(), (DataType::Text, b"Hello!!!".to_vec()));
+}
+
+#[test]
+fn unknown_datatypes_are_preserved() {
+    let (sim, mut cart) = cart(Model::HW2);
+    sim.inject_raw(b"DMA@\x42\x00\x00\x02hiCMPH");
+    
+    assert_eq!(cart.recv_debug().unwrap(), (DataType::Unknown(0x42), b"hi".to_vec()));
+    assert_eq!(u8::from(DataType::Unknown(0x42)), 0x42);
+}
+
+#[test]
+fn recv_debug_rejects_bad_frames() {
+    let (sim, mut cart) = cart(Model::HW2);
+    
+    sim.inject_raw(b"JUNKJUNKJUNK");
+    assert!(matches!(cart.recv_debug(), Err(Error::CommunicationFailed(_))));
+    assert_eq!(cart.poll_debug().unwrap(), None, "receive buffer should be purged");
+    
+    sim.inject_raw(b"DMA@\x01\x00\x00\x02hiCMPX");
+    assert!(matches!(cart.recv_debug(), Err(Error::CommunicationFailed(_))));
+}
+
+#[test]
+fn send_debug_pads_data() {
+    let (sim, mut cart) = cart(Model::HW2);
+    
+    cart.send_debug(DataType::Text, b"abc").unwrap();
+    cart.send_debug(DataType::RawBinary, &pattern(600)).unwrap();
+    
+    let mut padded = pattern(600);
+    padded.resize(1024, 0x00);
+    assert_eq!(sim.sent_debug(), vec![
+        (DataType::Text, b"abc\0".to_vec()),
+        (DataType::RawBinary, padded),
+    ]);
+}
+
+#[test]
+fn cart_opens_without_version() {
+    let mock = MockTransport::new();
+    mock.reply(b"JUNKJUNKJUNK");
+    let mut cart = SixtyFourDrive::new(mock).unwrap();
+    
+    assert_eq!(cart.model(), Model::HW2, "model should come from the USB identity");
+    let info = cart.info().unwrap();
+    assert_eq!((info.firmware, info.variant), (None, None));
+}
+
+#[test]
+fn send_debug_header_has_padded_length() {
+    let mock = MockTransport::new();
+    mock.reply(b"\x00\x00B\x00\x00\x00\x00\xCDCMP\x80");
+    let mut cart = SixtyFourDrive::new(mock.clone()).unwrap();
+    mock.take_written();
+    
+    mock.reply(b"CMP\x40");
+    cart.send_debug(DataType::Text, b"hello").unwrap();
+    assert_eq!(mock.take_written(), b"\x40CMD\x01\x00\x00\x08hello\0\0\0");
+    
+    mock.reply(b"CMP\x40");
+    cart.send_debug(DataType::RawBinary, &[0xAA; 513]).unwrap();
+    let written = mock.take_written();
+    assert_eq!(written.len(), 8 + 1024);
+    assert_eq!(written[..8], *b"\x40CMD\x02\x00\x04\x00");
+}
+
+#[test]
+fn save_uses_savetype_segment() {
+    let (sim, mut cart) = cart(Model::HW2);
+    let save = pattern(0x8000);
+    
+    cart.upload_save(SaveType::Sram256Kbit, &save, None).unwrap();
+    assert_eq!(sim.bank(2), save);
+    assert_eq!(cart.download_save(SaveType::Sram256Kbit, None).unwrap(), save);
+    
+    sim.set_bank(6, pattern(0x800));
+    assert_eq!(cart.download_save(SaveType::Eeprom4Kbit, None).unwrap(), pattern(512));
+    assert_eq!(cart.download_save(SaveType::Eeprom16Kbit, None).unwrap(), pattern(0x800));
+}
+
+#[test]
+fn save_without_data_is_rejected() {
+    let (sim, mut cart) = cart(Model::HW2);
+    
+    assert_eq!(cart.download_save(SaveType::Nothing, None), Err(Error::Unsupported));
+    assert_eq!(cart.upload_save(SaveType::Auto, &[0; 4], None), Err(Error::Unsupported));
+    assert!(matches!(cart.upload_save(SaveType::Eeprom4Kbit, &pattern(1024), None), Err(Error::CommunicationFailed(_))));
+    assert_eq!(sim.log(), vec![]);
+}
+
+#[test]
+fn verify_reuploads_mismatched_chunks() {
+    let (sim, mut cart) = cart(Model::HW2);
+    let rom = pattern(0x280000);
+    cart.upload_rom(&rom, None).unwrap();
+    
+    let mut bank = sim.bank(1);
+    bank[0x123456] ^= 0xFF;
+    bank[0x27FFFF] ^= 0xFF;
+    sim.set_bank(1, bank);
+    
+    assert_eq!(cart.verify_rom(&rom, None).unwrap(), vec![0x100000, 0x200000]);
+    assert_eq!(sim.bank(1), rom);
+    assert_eq!(cart.verify_rom(&rom, None).unwrap(), vec![]);
+}
+
+#[test]
+fn verify_reports_persistent_mismatches() {
+    let (sim, mut cart) = cart(Model::HW2);
+    let rom = pattern(0x180000);
+    sim.corrupt_writes(1, 0x100004);
+    cart.upload_rom(&rom, None).unwrap();
+    
+    assert_eq!(cart.verify_rom(&rom, None), Err(Error::CommunicationFailed("ROM verification failed at offsets: 0x00100000".into())));
+    assert_eq!(sim.log().last(), Some(&SimCommand::DumpToPc { addr: 0x160000, bank_id_len: 0x01020000 }));
+}
+
+#[test]
+fn progress_is_reported_per_chunk() {
+    let (_sim, mut cart) = cart(Model::HW2);
+    let mut reports = vec![];
+    
+    cart.upload_rom(&pattern(0x1400000), Some(&mut |progress: TransferProgress| reports.push(progress.bytes_done))).unwrap();
+    assert_eq!(reports, vec![0, 0x800000, 0x1000000, 0x1400000]);
+    
+    reports.clear();
+    cart.download_rom(0x30000, Some(&mut |progress: TransferProgress| reports.push(progress.bytes_done))).unwrap();
+    assert_eq!(reports, vec![0, 0x20000, 0x30000]);
+}
+
+#[test]
+fn info_reports_version() {
+    let (_sim, mut cart) = cart(Model::HW2);
+    
+    assert_eq!(cart.info().unwrap(), CartInfo {
+        model: "64drive HW2".into(),
+        firmware: Some("2.05".into()),
+        variant: Some(0x00004232),
+        serial_number: "SIM64HW2".into(),
+        capabilities: Capabilities {
+            set_cic: true,
+            set_savetype: true,
+            eeprom_saves: true,
+            max_rom_length: 240 * 1024 * 1024,
+        },
+    });
+}
+
+#[test]
+fn model_is_detected_from_version() {
+    let sim = SimSixtyFourDrive::new(Model::HW2);
+    sim.set_version([0, 0, b'A', b'2', 0, 0, 0, 200]);
+    let mut cart = SixtyFourDrive::new(sim.clone()).unwrap();
+    
+    assert_eq!(sim.log(), vec![SimCommand::VersionRequest]);
+    assert_eq!(cart.model(), Model::HW1);
+    
+    let info = cart.info().unwrap();
+    assert_eq!(info.firmware, Some("2.00".into()));
+    assert!(!info.capabilities.set_cic);
+    assert_eq!(info.capabilities.max_rom_length, 64 * 1024 * 1024);
+}
+
+#[test]
+fn unknown_variant_falls_back_to_identity() {
+    let sim = SimSixtyFourDrive::new(Model::HW1);
+    sim.set_version([0; 8]);
+    
+    assert_eq!(SixtyFourDrive::new(sim).unwrap().model(), Model::HW1);
+}
+
+#[test]
+fn firmware_must_match_a_known_release() {
+    let data = pattern(0x1000);
+    let known = [KnownFirmware { model: Model::HW2, revision: 206, length: 0x1000, crc: CRC.checksum(&data) }];
+    
+    assert_eq!(rpk::identify(&data, &known), Ok(&known[0]));
+    assert!(rpk::identify(&data[..0xFFF], &known).is_err(), "truncated");
+    
+    let mut corrupted = data.clone();
+    corrupted[0x30] ^= 0xFF;
+    assert!(rpk::identify(&corrupted, &known).is_err(), "bad checksum");
+}
+
+#[test]
+fn unknown_firmware_is_refused() {
+    let (sim, mut cart) = cart(Model::HW2);
+    
+    assert!(matches!(cart.update_firmware(&pattern(0x1000), None), Err(Error::InvalidFirmware(_))));
+    assert!(matches!(cart.update_firmware(b"not a firmware file", None), Err(Error::InvalidFirmware(_))));
+    
+    assert_eq!(sim.log(), vec![]);
+}
+
+#[test]
+fn extended_addressing_follows_rom_size() {
+    let (sim, mut cart) = cart(Model::HW2);
+    
+    cart.upload_rom(&pattern(0x4000000), None).unwrap();
+    assert!(!sim.log().contains(&SimCommand::SetCiExtended(0)), "64 MiB fits without extended addressing, which is already off");
+    
+    sim.clear_log();
+    cart.upload_rom(&pattern(0x4000004), None).unwrap();
+    assert_eq!(sim.log()[0], SimCommand::SetCiExtended(1));
+    assert_eq!(sim.log().len(), 10);
+    
+    sim.clear_log();
+    cart.upload_rom(&pattern(0x4000008), None).unwrap();
+    assert!(!sim.log().contains(&SimCommand::SetCiExtended(1)), "already enabled");
+    
+    sim.clear_log();
+    cart.upload_rom(&pattern(0x1000), None).unwrap();
+    assert_eq!(sim.log()[0], SimCommand::SetCiExtended(0), "disabled again for a smaller ROM");
+    
+    sim.clear_log();
+    cart.upload_rom(&pattern(0x1000), None).unwrap();
+    assert!(!sim.log().contains(&SimCommand::SetCiExtended(0)));
+}
+
+#[test]
+fn hw1_refuses_extended_addressing() {
+    let (sim, mut cart) = cart(Model::HW1);
+    
+    assert!(matches!(cart.upload_rom(&pattern(0x4000004), None), Err(Error::CommunicationFailed(_))));
+    assert_eq!(cart.set_extended(true), Err(Error::Unsupported));
+    assert_eq!(sim.log(), vec![]);
+}
+
+#[test]
+fn stadium_flashram_uses_bank_5_on_hw1() {
+    let (sim, mut cart) = cart(Model::HW1);
+    let save = pattern(0x20000);
+    
+    cart.upload_save(SaveType::FlashRam1MbitStadium, &save, None).unwrap();
+    assert_eq!(sim.bank(5), save);
+    assert_eq!(sim.bank(4), vec![]);
+    assert_eq!(cart.download_save(SaveType::FlashRam1MbitStadium, None).unwrap(), save);
+    
+    cart.upload_save(SaveType::FlashRam1Mbit, &pattern(16), None).unwrap();
+    assert_eq!(sim.bank(4), pattern(16));
+}
+
+#[test]
+fn configured_savetype_selects_flashram_bank() {
+    let (sim, mut cart) = cart(Model::HW1);
+    
+    cart.upload(Segment::FlashRam, 0, &[1; 16], None).unwrap();
+    cart.set_savetype(SaveType::FlashRam1MbitStadium).unwrap();
+    cart.upload(Segment::FlashRam, 0, &[2; 16], None).unwrap();
+    
+    assert_eq!(sim.bank(4), vec![1; 16]);
+    assert_eq!(sim.bank(5), vec![2; 16]);
+    assert_eq!(cart.download(Segment::FlashRam, 0, 16, None).unwrap(), vec![2; 16]);
+    assert_eq!(sim.log()[1], SimCommand::SetSaveType(6));
+}
+
+#[test]
+fn stadium_flashram_uses_normal_bank_on_hw2() {
+    let (sim, mut cart) = cart(Model::HW2);
+    
+    cart.upload_save(SaveType::FlashRam1MbitStadium, &pattern(16), None).unwrap();
+    assert_eq!(sim.bank(4), pattern(16));
+    assert_eq!(sim.bank(5), vec![]);
+}
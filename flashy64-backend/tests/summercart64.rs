@@ -0,0 +1,116 @@
+mod common;
+
+use flashy64_backend::{Error, Flashcart};
+use flashy64_backend::carts::{Cic, SaveType};
+use flashy64_backend::carts::summercart64::{Command, Config, SummerCart64};
+use flashy64_backend::unfloader::DataType;
+use common::MockTransport;
+
+fn packet(token: &[u8; 3], id: u8, arg0: u32, arg1: u32) -> Vec<u8> {
+    let mut data = token.to_vec();
+    data.push(id);
+    data.extend(arg0.to_be_bytes());
+    data.extend(arg1.to_be_bytes());
+    
+    data
+}
+
+fn response(token: &[u8; 3], id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut data = token.to_vec();
+    data.push(id);
+    data.extend((payload.len() as u32).to_be_bytes());
+    data.extend(payload);
+    
+    data
+}
+
+fn cart() -> (MockTransport, SummerCart64<MockTransport>) {
+    let mock = MockTransport::new();
+    mock.reply(&response(b"CMP", b'v', b"SCv2"));
+    mock.reply(&response(b"CMP", b'R', &[]));
+    let cart = SummerCart64::new(mock.clone()).unwrap();
+    
+    assert_eq!(mock.take_written(), [packet(b"CMD", b'v', 0, 0), packet(b"CMD", b'R', 0, 0)].concat());
+    (mock, cart)
+}
+
+#[test]
+fn commands_are_framed_with_arguments_and_data() {
+    assert_eq!(Command::ConfigGet(Config::TvType).encode_packet(), packet(b"CMD", b'c', 8, 0));
+    assert_eq!(Command::MemoryRead { addr: 0x03FE0000, length: 0x8000 }.encode_packet(), packet(b"CMD", b'm', 0x03FE0000, 0x8000));
+    assert_eq!(Command::MemoryWrite { addr: 0x10, data: vec![1, 2, 3] }.encode_packet(), [packet(b"CMD", b'M', 0x10, 3), vec![1, 2, 3]].concat());
+    assert_eq!(Command::UsbWrite { kind: DataType::Text, data: b"hi".to_vec() }.encode_packet(), [packet(b"CMD", b'U', 1, 2), b"hi".to_vec()].concat());
+}
+
+#[test]
+fn identifier_mismatch_is_rejected() {
+    let mock = MockTransport::new();
+    mock.reply(&response(b"CMP", b'v', b"SCv1"));
+    
+    assert!(matches!(SummerCart64::new(mock), Err(Error::CommunicationFailed(_))));
+}
+
+#[test]
+fn cic_seed_is_sent_as_config() {
+    let (mock, mut cart) = cart();
+    
+    for (cic, seed) in [(Cic::Var6101, 0x13F), (Cic::Var6102, 0x03F), (Cic::Var7102, 0x13F), (Cic::VarX103, 0x078), (Cic::VarX105, 0x091), (Cic::VarX106, 0x085), (Cic::Var5101, 0x0AC)] {
+        mock.reply(&response(b"CMP", b'C', &[]));
+        cart.set_cic(cic).unwrap();
+        assert_eq!(mock.take_written(), packet(b"CMD", b'C', 7, seed), "{cic}");
+    }
+    
+    assert!(matches!(cart.set_cic(Cic::Auto), Err(Error::Unsupported)));
+    assert!(mock.take_written().is_empty());
+}
+
+#[test]
+fn error_response_fails_the_command() {
+    let (mock, mut cart) = cart();
+    mock.reply(&response(b"ERR", b'C', &[]));
+    
+    assert!(matches!(cart.set_savetype(SaveType::Sram256Kbit), Err(Error::CommunicationFailed(_))));
+    assert_eq!(mock.take_written(), packet(b"CMD", b'C', 6, 3));
+}
+
+#[test]
+fn response_id_mismatch_fails_the_command() {
+    let (mock, mut cart) = cart();
+    mock.reply(&response(b"CMP", b'c', &[]));
+    
+    assert!(matches!(cart.set_savetype(SaveType::Sram256Kbit), Err(Error::CommunicationFailed(_))));
+}
+
+#[test]
+fn upload_is_split_into_1mib_chunks() {
+    let (mock, mut cart) = cart();
+    let rom: Vec<u8> = (0..0x180000).map(|i| i as u8).collect();
+    mock.reply(&response(b"CMP", b'M', &[]));
+    mock.reply(&response(b"CMP", b'M', &[]));
+    
+    cart.upload_rom(&rom, None).unwrap();
+    assert_eq!(mock.take_written(), [
+        packet(b"CMD", b'M', 0x000000, 0x100000), rom[..0x100000].to_vec(),
+        packet(b"CMD", b'M', 0x100000, 0x80000), rom[0x100000..].to_vec(),
+    ].concat());
+}
+
+#[test]
+fn debug_packets_received_during_commands_are_queued() {
+    let (mock, mut cart) = cart();
+    mock.reply(&response(b"PKT", b'U', b"\x01\x00\x00\x05hello"));
+    mock.reply(&response(b"CMP", b'C', &[]));
+    
+    cart.set_savetype(SaveType::Nothing).unwrap();
+    assert_eq!(cart.poll_debug().unwrap(), Some((DataType::Text, b"hello".to_vec())));
+    assert_eq!(cart.poll_debug().unwrap(), None);
+}
+
+#[test]
+fn debug_data_is_sent_as_usb_write() {
+    let (mock, mut cart) = cart();
+    mock.reply(&response(b"CMP", b'U', &[]));
+    
+    cart.send_debug(DataType::RawBinary, &[0xDE, 0xAD]).unwrap();
+    assert_eq!(mock.take_written(), [packet(b"CMD", b'U', 2, 2), vec![0xDE, 0xAD]].concat());
+}
@@ -0,0 +1,20 @@
+use flashy64_backend::transport::Identity;
+
+#[test]
+fn channel_a_is_named_like_d2xx() {
+    // What libusb reads from a 64drive HW1's descriptors, and what D2XX lists for its first channel.
+    let descriptors = Identity {
+        vendor_id: 0x0403,
+        product_id: 0x6010,
+        serial_number: "FT1XYZ".into(),
+        description: "64drive USB device".into(),
+    };
+    let d2xx = Identity {
+        vendor_id: 0x0403,
+        product_id: 0x6010,
+        serial_number: "FT1XYZA".into(),
+        description: "64drive USB device A".into(),
+    };
+    
+    assert_eq!(descriptors.channel_a(), d2xx);
+}
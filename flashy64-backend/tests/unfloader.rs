@@ -0,0 +1,135 @@
+use flashy64_backend::unfloader::{DataType, Heartbeat, ProtocolVersion, Session};
+use flashy64_backend::unfloader::binary::BinaryWriter;
+use flashy64_backend::unfloader::gdb::{Decoder, encode_packet, GdbEvent};
+use flashy64_backend::unfloader::screenshot::{Screenshot, ScreenshotHeader};
+
+fn header(kind: u32, depth: u32, width: u32, height: u32) -> Vec<u8> {
+    [kind, depth, width, height].iter().flat_map(|word| word.to_be_bytes()).collect()
+}
+
+#[test]
+fn screenshot_header_is_parsed() {
+    assert_eq!(ScreenshotHeader::parse(&header(0x04, 2, 320, 240)), Ok(ScreenshotHeader { depth: 2, width: 320, height: 240 }));
+    assert_eq!(ScreenshotHeader::parse(&header(0x04, 4, 640, 480)).unwrap().frame_length(), Some(640 * 480 * 4));
+    
+    assert!(ScreenshotHeader::parse(&header(0x02, 2, 320, 240)).is_err());
+    assert!(ScreenshotHeader::parse(&header(0x04, 3, 320, 240)).is_err());
+    assert!(ScreenshotHeader::parse(&header(0x04, 2, 320, 240)[..12]).is_err());
+    assert!(ScreenshotHeader::parse(&header(0x04, 2, 0, 240)).is_err(), "zero width");
+    assert!(ScreenshotHeader::parse(&header(0x04, 2, 320, 0)).is_err(), "zero height");
+    assert!(ScreenshotHeader::parse(&header(0x04, 4, 0xFFFFFFFF, 0xFFFFFFFF)).is_err(), "oversized");
+}
+
+#[test]
+fn rgba5551_is_expanded_to_rgb() {
+    let header = ScreenshotHeader { depth: 2, width: 4, height: 1 };
+    let data = [0xF8, 0x01, 0x07, 0xC0, 0x00, 0x3E, 0x42, 0x10];
+    
+    assert_eq!(Screenshot::decode(&header, &data).unwrap().pixels, vec![
+        0xFF, 0x00, 0x00,
+        0x00, 0xFF, 0x00,
+        0x00, 0x00, 0xFF,
+        0x42, 0x42, 0x42,
+    ]);
+}
+
+#[test]
+fn rgba8888_drops_alpha() {
+    let header = ScreenshotHeader { depth: 4, width: 1, height: 2 };
+    let data = [0x12, 0x34, 0x56, 0x00, 0xAB, 0xCD, 0xEF, 0xFF];
+    
+    assert_eq!(Screenshot::decode(&header, &data).unwrap().pixels, vec![0x12, 0x34, 0x56, 0xAB, 0xCD, 0xEF]);
+}
+
+#[test]
+fn short_framebuffer_is_rejected() {
+    let header = ScreenshotHeader { depth: 2, width: 320, height: 240 };
+    
+    assert!(Screenshot::decode(&header, &vec![0; 320 * 240]).is_err());
+    
+    let header = ScreenshotHeader { depth: 4, width: u32::MAX, height: u32::MAX };
+    assert_eq!(header.frame_length(), None);
+    assert!(Screenshot::decode(&header, &[0; 16]).is_err());
+}
+
+#[test]
+fn png_round_trips() {
+    let header = ScreenshotHeader { depth: 4, width: 3, height: 2 };
+    let data: Vec<u8> = (0..24).collect();
+    let screenshot = Screenshot::decode(&header, &data).unwrap();
+    
+    let mut png = vec![];
+    screenshot.write_png(&mut png).unwrap();
+    
+    let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    assert_eq!((info.width, info.height, info.color_type), (3, 2, png::ColorType::Rgb));
+    assert_eq!(pixels, screenshot.pixels);
+}
+
+#[test]
+fn binaries_are_numbered_without_overwriting() {
+    let dir = std::env::temp_dir().join(format!("flashy64-binaries-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("binary-0002.bin"), b"old").unwrap();
+    
+    let mut writer = BinaryWriter::new(&dir);
+    let first = writer.write(b"abc").unwrap();
+    let second = writer.write(&[0xFF; 1024]).unwrap();
+    
+    assert_eq!(first.path, dir.join("binary-0001.bin"));
+    assert_eq!((first.length, first.md5.as_str()), (3, "900150983cd24fb0d6963f7d28e17f72"));
+    assert_eq!(second.path, dir.join("binary-0003.bin"));
+    assert_eq!(std::fs::read(&second.path).unwrap(), vec![0xFF; 1024]);
+    assert_eq!(std::fs::read(dir.join("binary-0002.bin")).unwrap(), b"old");
+    
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn gdb_packets_are_framed_with_checksum() {
+    assert_eq!(encode_packet(b"OK"), b"$OK#9a");
+    assert_eq!(encode_packet(b""), b"$#00");
+}
+
+#[test]
+fn gdb_stream_is_decoded() {
+    let mut decoder = Decoder::new();
+    
+    assert_eq!(decoder.push(b"+$qSupported#37"), vec![GdbEvent::Ack, GdbEvent::Packet(b"qSupported".to_vec())]);
+    assert_eq!(decoder.push(b"$g#6"), vec![]);
+    assert_eq!(decoder.push(b"7-\x03"), vec![GdbEvent::Packet(b"g".to_vec()), GdbEvent::Nack, GdbEvent::Interrupt]);
+    assert_eq!(decoder.push(b"$m0,4#00"), vec![GdbEvent::BadChecksum]);
+    assert_eq!(decoder.push(b"$?#3F"), vec![GdbEvent::Packet(b"?".to_vec())]);
+}
+
+#[test]
+fn heartbeat_is_parsed() {
+    assert_eq!(Heartbeat::parse(&[0x00, 0x02, 0x00, 0x01]), Ok(Heartbeat { protocol: ProtocolVersion::V2, version: 1 }));
+    assert!(Heartbeat::parse(&[0x00, 0x02]).is_err());
+}
+
+#[test]
+fn protocol_version_controls_padding() {
+    assert_eq!(ProtocolVersion::V1.data_padding(5), 0);
+    assert_eq!(ProtocolVersion::V2.data_padding(5), 3);
+    assert_eq!(ProtocolVersion::V2.data_padding(8), 0);
+}
+
+#[test]
+fn session_tracks_heartbeats_until_rom_upload() {
+    let mut session = Session::new();
+    assert_eq!(session.protocol(), ProtocolVersion::V1);
+    
+    session.receive(DataType::Text, &[0x00, 0x02, 0x00, 0x01]);
+    assert_eq!(session.protocol(), ProtocolVersion::V1, "only heartbeats change the version");
+    session.receive(DataType::Heartbeat, &[0x00, 0x02, 0x00, 0x01]);
+    assert_eq!(session.protocol(), ProtocolVersion::V2);
+    session.receive(DataType::Heartbeat, &[0x00]);
+    assert_eq!(session.protocol(), ProtocolVersion::V2, "invalid heartbeats are ignored");
+    
+    session.rom_uploaded();
+    assert_eq!(session.protocol(), ProtocolVersion::V1);
+}
@@ -0,0 +1,317 @@
+use std::io::{stdout, Stdout, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crossterm::{cursor, event, queue, terminal};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{Clear, ClearType};
+use log::{error, info};
+use flashy64_backend::Flashcart;
+use flashy64_backend::carts::Cic;
+use flashy64_backend::elf::Symbolizer;
+use flashy64_backend::unfloader::DataType;
+use flashy64_backend::unfloader::binary::BinaryWriter;
+use flashy64_backend::unfloader::screenshot::{Screenshot, ScreenshotHeader};
+
+/// Set while the console is running. The terminal is in raw mode during that time, so log output
+/// must use `\r\n` line endings.
+pub static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+const PROMPT: &str = "> ";
+
+/// How long to wait for keyboard input, between checks for received packets.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+const HELP: &str = "Lines are sent to the cartridge as UNFLoader text. Start a line with '//' to send a line beginning with '/'.
+Local commands:
+  /quit      Exit the console (also CTRL+C)
+  /clear     Clear the screen (also CTRL+L)
+  /reupload  Upload the ROM again, and boot it
+  /help      Show this message";
+
+/// Where the console saves data received from the cartridge, and how it reuploads the ROM.
+pub struct Options {
+    /// Directory for screenshots, saved as PNG files.
+    pub screenshot_dir: PathBuf,
+    /// Directory for binary data, saved as numbered files.
+    pub binary_dir: PathBuf,
+    /// Print the length and MD5 hash of each binary packet.
+    pub binary_summary: bool,
+    /// Symbols of the running game, used to annotate addresses in received text.
+    pub symbols: Option<Symbolizer>,
+    /// IPL3 file used to convert an ELF to a ROM, when reuploading.
+    pub ipl3: Option<PathBuf>,
+    /// CIC of the ELF's IPL3, or `None` to detect it.
+    pub cic: Option<Cic>,
+}
+
+/// Interactive UNFLoader console.
+/// 
+/// Text received from the cartridge is printed above the input line, and is kept in the terminal's
+/// scrollback. Typed lines are sent to the cartridge as [`DataType::Text`] packets. Screenshots and
+/// binary data are saved to the directories given in [`Options`].
+pub struct Console<'a> {
+    cart: &'a mut dyn Flashcart,
+    rom: Option<&'a Path>,
+    options: Options,
+    binaries: BinaryWriter,
+    /// Format of the next screenshot, from the most recent header packet.
+    screenshot_header: Option<ScreenshotHeader>,
+    stdout: Stdout,
+    input: String,
+    /// Received text after the last newline. It's printed on its own line above the prompt, until
+    /// the rest of the line arrives.
+    partial: String,
+    /// Number of terminal rows the partial line was printed on, since long lines wrap.
+    partial_rows: u16,
+    /// Number of consecutive failed polls. The cartridge is polled less often while it keeps failing.
+    poll_errors: u32,
+    next_poll: Instant,
+    running: bool,
+}
+impl<'a> Console<'a> {
+    pub fn new(cart: &'a mut dyn Flashcart, rom: Option<&'a Path>, options: Options) -> Self {
+        Self {
+            cart,
+            rom,
+            binaries: BinaryWriter::new(&options.binary_dir),
+            options,
+            screenshot_header: None,
+            stdout: stdout(),
+            input: String::new(),
+            partial: String::new(),
+            partial_rows: 0,
+            poll_errors: 0,
+            next_poll: Instant::now(),
+            running: false,
+        }
+    }
+    
+    /// Runs the console until the user quits.
+    pub fn run(&mut self) -> crossterm::Result<()> {
+        terminal::enable_raw_mode()?;
+        ACTIVE.store(true, Ordering::SeqCst);
+        
+        self.running = true;
+        let result = self.event_loop();
+        
+        ACTIVE.store(false, Ordering::SeqCst);
+        terminal::disable_raw_mode()?;
+        println!();
+        
+        result
+    }
+    
+    fn event_loop(&mut self) -> crossterm::Result<()> {
+        self.draw_prompt()?;
+        
+        while self.running {
+            if Instant::now() >= self.next_poll {
+                self.poll()?;
+            }
+            
+            if event::poll(POLL_INTERVAL)? {
+                if let Event::Key(key) = event::read()? {
+                    self.handle_key(key)?;
+                }
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Checks the cartridge for a packet. Only the first of a run of errors is printed, and the delay
+    /// before the next attempt doubles with each one, up to about a second.
+    fn poll(&mut self) -> crossterm::Result<()> {
+        match self.cart.poll_debug() {
+            Ok(packet) => {
+                if self.poll_errors > 0 {
+                    self.poll_errors = 0;
+                    self.print_line("Cartridge is responding again.")?;
+                }
+                match packet {
+                    Some((kind, data)) => self.handle_packet(kind, data),
+                    None => Ok(()),
+                }
+            },
+            Err(err) => {
+                if self.poll_errors == 0 {
+                    self.print_line(&format!("Err: {err:?}. Retrying until the cartridge responds."))?;
+                }
+                self.next_poll = Instant::now() + POLL_INTERVAL * (1 << self.poll_errors.min(7));
+                self.poll_errors += 1;
+                Ok(())
+            }
+        }
+    }
+    
+    fn handle_packet(&mut self, kind: DataType, data: Vec<u8>) -> crossterm::Result<()> {
+        use DataType::*;
+        
+        match kind {
+            Text => {
+                let text = String::from_utf8_lossy(&data);
+                match self.options.symbols {
+                    Some(ref symbols) => self.print_text(&symbols.annotate(&text)),
+                    None => self.print_text(&text),
+                }
+            },
+            Header => match ScreenshotHeader::parse(&data) {
+                Ok(header) => {
+                    self.screenshot_header = Some(header);
+                    Ok(())
+                },
+                Err(err) => self.print_line(&format!("Unsupported header: {err}")),
+            },
+            RawBinary => match self.binaries.write(&data) {
+                Ok(saved) if self.options.binary_summary => self.print_line(&format!("Binary data saved to {}. {} bytes, MD5 {}", saved.path.display(), saved.length, saved.md5)),
+                Ok(saved) => self.print_line(&format!("Binary data saved to {}.", saved.path.display())),
+                Err(err) => self.print_line(&format!("Err: Unable to save binary data: {err}")),
+            },
+            Screenshot => match self.save_screenshot(&data) {
+                Ok(path) => self.print_line(&format!("Screenshot saved to {}.", path.display())),
+                Err(err) => self.print_line(&format!("Err: Unable to save screenshot: {err}")),
+            },
+            Heartbeat => self.print_line(&format!("Cartridge is using UNFLoader protocol version {}.", self.cart.protocol_version().0)),
+            _ => self.print_line(&format!("Unsupported data type: {kind:?}")),
+        }
+    }
+    
+    /// Decodes a screenshot using the preceding header, and writes it to a timestamped PNG file.
+    fn save_screenshot(&mut self, data: &[u8]) -> Result<PathBuf, String> {
+        let header = self.screenshot_header.take().ok_or("no header was received before the screenshot")?;
+        let screenshot = Screenshot::decode(&header, data)?;
+        
+        let name = chrono::Local::now().format("screenshot-%Y%m%d-%H%M%S%.3f.png").to_string();
+        let path = self.options.screenshot_dir.join(name);
+        std::fs::create_dir_all(&self.options.screenshot_dir).map_err(|err| format!("{err}"))?;
+        let file = std::fs::File::create(&path).map_err(|err| format!("{err}"))?;
+        screenshot.write_png(std::io::BufWriter::new(file))?;
+        
+        Ok(path)
+    }
+    
+    fn handle_key(&mut self, key: KeyEvent) -> crossterm::Result<()> {
+        match (key.code, key.modifiers) {
+            (KeyCode::Char('c'), KeyModifiers::CONTROL) => self.running = false,
+            (KeyCode::Char('l'), KeyModifiers::CONTROL) => self.clear()?,
+            // Other shortcuts aren't text, so they shouldn't end up in the input line.
+            (KeyCode::Char(_), modifiers) if modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => (),
+            (KeyCode::Char(c), _) => {
+                self.input.push(c);
+                queue!(self.stdout, Print(c))?;
+                self.stdout.flush()?;
+            },
+            (KeyCode::Backspace, _) => {
+                self.input.pop();
+                self.draw_prompt()?;
+            },
+            (KeyCode::Enter, _) => {
+                let line = std::mem::take(&mut self.input);
+                self.submit(&line)?;
+            },
+            _ => (),
+        }
+        
+        Ok(())
+    }
+    
+    fn submit(&mut self, line: &str) -> crossterm::Result<()> {
+        let text = match line.strip_prefix('/') {
+            Some(rest) if rest.starts_with('/') => rest,
+            Some(_) => return self.run_command(line.trim()),
+            None => line,
+        };
+        
+        match self.cart.send_debug(DataType::Text, text.as_bytes()) {
+            Ok(()) => self.draw_prompt(),
+            Err(err) => self.print_line(&format!("Err: {err:?}")),
+        }
+    }
+    
+    fn run_command(&mut self, command: &str) -> crossterm::Result<()> {
+        match command {
+            "/quit" | "/exit" => {
+                self.running = false;
+                Ok(())
+            },
+            "/clear" => self.clear(),
+            "/reupload" => self.reupload(),
+            "/help" => self.print_line(HELP),
+            _ => self.print_line(&format!("Unknown command: {command} (type /help for a list of commands)")),
+        }
+    }
+    
+    fn reupload(&mut self) -> crossterm::Result<()> {
+        let path = match self.rom {
+            Some(path) => path,
+            None => return self.print_line("No ROM to upload. Use --upload to specify one."),
+        };
+        
+        self.print_line(&format!("Uploading {}...", path.display()))?;
+        let result = crate::read_rom(path, self.options.ipl3.as_deref(), self.options.cic)
+            .and_then(|data| self.cart.upload_rom(&data, None).map_err(|err| format!("{err:?}")))
+            .and_then(|_| self.cart.boot().map_err(|err| format!("{err:?}")));
+        
+        match result {
+            Ok(()) => self.print_line("ROM Upload Complete."),
+            Err(err) => self.print_line(&format!("Err: {err}")),
+        }
+    }
+    
+    fn clear(&mut self) -> crossterm::Result<()> {
+        queue!(self.stdout, Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+        self.partial.clear();
+        self.partial_rows = 0;
+        self.draw_prompt()
+    }
+    
+    /// Prints a complete line of output above the prompt.
+    fn print_line(&mut self, line: &str) -> crossterm::Result<()> {
+        let mut text = line.to_owned();
+        if !self.partial.is_empty() {
+            text.insert(0, '\n');
+        }
+        text.push('\n');
+        
+        self.print_text(&text)
+    }
+    
+    /// Prints received text above the prompt. Text after the last newline is held back in `partial`.
+    fn print_text(&mut self, text: &str) -> crossterm::Result<()> {
+        queue!(self.stdout, Print('\r'), Clear(ClearType::CurrentLine))?;
+        if self.partial_rows > 0 {
+            queue!(self.stdout, cursor::MoveUp(self.partial_rows), Print('\r'), Clear(ClearType::FromCursorDown))?;
+        }
+        
+        self.partial.push_str(text);
+        let split = self.partial.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let rest = self.partial.split_off(split);
+        
+        queue!(self.stdout, Print(self.partial.replace('\n', "\r\n")))?;
+        self.partial = rest;
+        self.partial_rows = 0;
+        if !self.partial.is_empty() {
+            let width = terminal::size()?.0.max(1) as usize;
+            self.partial_rows = self.partial.chars().count().div_ceil(width).max(1) as u16;
+            queue!(self.stdout, Print(&self.partial), Print("\r\n"))?;
+        }
+        
+        self.draw_prompt()
+    }
+    
+    fn draw_prompt(&mut self) -> crossterm::Result<()> {
+        queue!(self.stdout, Print('\r'), Clear(ClearType::CurrentLine), Print(PROMPT), Print(&self.input))?;
+        self.stdout.flush()
+    }
+}
+
+/// Runs the interactive console, logging any terminal errors.
+pub fn run(cart: &mut dyn Flashcart, rom: Option<&Path>, options: Options) {
+    info!("UNFLoader console started. Type /help for a list of commands.");
+    
+    if let Err(err) = Console::new(cart, rom, options).run() {
+        error!("Console error: {err}");
+    }
+}
@@ -0,0 +1,90 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+use log::{debug, error, info, warn};
+use flashy64_backend::Flashcart;
+use flashy64_backend::unfloader::DataType;
+use flashy64_backend::unfloader::gdb::{Decoder, encode_packet, GdbEvent, INTERRUPT};
+
+/// How long to wait for data from GDB, before checking the cartridge for packets again.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Waits for GDB to connect on the given address, then tunnels RSP packets between it and the
+/// cartridge until GDB disconnects.
+pub fn run(cart: &mut dyn Flashcart, addr: &str) {
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Unable to listen on {addr}: {err}");
+            return;
+        }
+    };
+    
+    info!("Waiting for GDB on {addr}. Connect with: target remote {addr}");
+    let stream = match listener.accept() {
+        Ok((stream, peer)) => {
+            info!("GDB connected from {peer}.");
+            stream
+        },
+        Err(err) => {
+            error!("Err: {err}");
+            return;
+        }
+    };
+    
+    match bridge(cart, stream) {
+        Ok(()) => info!("GDB disconnected."),
+        Err(err) => error!("Err: {err}"),
+    }
+}
+
+fn bridge(cart: &mut dyn Flashcart, mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    
+    let mut decoder = Decoder::new();
+    let mut buf = [0u8; 4096];
+    // Kept until GDB acknowledges it, in case it asks for it again.
+    let mut last_sent: Vec<u8> = vec![];
+    
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(length) => for event in decoder.push(&buf[..length]) {
+                match event {
+                    GdbEvent::Packet(data) => {
+                        stream.write_all(b"+")?;
+                        debug!("GDB -> cart: {}", String::from_utf8_lossy(&data));
+                        if let Err(err) = cart.send_debug(DataType::RdbPacket, &data) {
+                            error!("Err: {err:?}");
+                        }
+                    },
+                    GdbEvent::BadChecksum => {
+                        warn!("Packet from GDB has a bad checksum. Asking for it again.");
+                        stream.write_all(b"-")?;
+                    },
+                    GdbEvent::Interrupt => if let Err(err) = cart.send_debug(DataType::RdbPacket, &[INTERRUPT]) {
+                        error!("Err: {err:?}");
+                    },
+                    GdbEvent::Ack => last_sent.clear(),
+                    GdbEvent::Nack => stream.write_all(&last_sent)?,
+                }
+            },
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+            Err(err) => return Err(err),
+        }
+        
+        match cart.poll_debug() {
+            Ok(Some((DataType::RdbPacket, data))) => {
+                debug!("cart -> GDB: {}", String::from_utf8_lossy(&data));
+                last_sent = encode_packet(&data);
+                stream.write_all(&last_sent)?;
+            },
+            // Text is still printed, since the stub may log what it's doing.
+            Ok(Some((DataType::Text, data))) => info!("{}", String::from_utf8_lossy(&data).trim_end()),
+            Ok(Some((kind, _))) => debug!("Ignoring {kind:?} data while GDB is connected."),
+            Ok(None) => (),
+            Err(err) => error!("Err: {err:?}"),
+        }
+    }
+}
@@ -1,19 +1,37 @@
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
 use bpaf::Bpaf;
 use env_logger::Builder;
 use env_logger::fmt::Color::*;
 use log::{debug, error, info, LevelFilter, warn};
-use flashy64_backend::carts::{Cic, SaveType};
 use flashy64_backend::Error;
+use flashy64_backend::carts::{Cic, SaveType};
+use flashy64_backend::elf;
+use flashy64_backend::elf::Symbolizer;
+use flashy64_backend::report::{Report, ReportFormat, TestStatus};
+use flashy64_backend::rom;
+use flashy64_backend::rom::{ByteOrder, RomHeader};
+use crate::progress::ProgressBar;
+use crate::runner::Timeout;
+
+mod console;
+mod gdb;
+mod progress;
+mod runner;
 
 #[derive(Debug, Bpaf)]
 #[bpaf(options, version, generate(args))]
 struct Args {
-    /// Upload file at this path to the cartridge's ROM space.
+    /// Upload file at this path to the cartridge's ROM space. ELF files are converted to a ROM first, using --ipl3.
     #[bpaf(long, short)]
     upload: Option<PathBuf>,
     
+    /// IPL3 used when uploading an ELF file. Either a ROM, whose header and IPL3 are copied, or a bare
+    /// 0xFC0 byte IPL3 dump. The CIC is detected from it, unless --cic is given.
+    #[bpaf(long, argument("FILE"))]
+    ipl3: Option<PathBuf>,
+    
     /// Specifies which CIC variant should be used. Note! 64drive HW1 does not support setting the CIC.
     ///   Options: auto, 6101, 6102, 7101, 7102, x103, x105, x106, or 5101
     #[bpaf(long, short)]
@@ -32,15 +50,48 @@ struct Args {
     #[bpaf(long, short)]
     device: Option<String>,
     
-    /// Enables UNFLoader support. Program will stay open until the user quits (CTRL+C).
-    ///   (Sending data via UNFLoader to cartridge is not supported yet)
+    /// Opens an interactive UNFLoader console. Received text is printed, and typed lines are sent to the
+    /// cartridge. Type /help in the console for a list of commands, or /quit (CTRL+C) to exit.
     #[bpaf(long)]
     unf: bool,
     
+    /// Directory where screenshots received by the UNFLoader console are saved as PNG files. Defaults to the
+    /// current directory.
+    #[bpaf(long, argument("DIR"))]
+    screenshot_dir: Option<PathBuf>,
+    
+    /// Directory where binary data received by the UNFLoader console is saved, as numbered files
+    /// (binary-0001.bin, ...). Defaults to the current directory.
+    #[bpaf(long, argument("DIR"))]
+    binary_dir: Option<PathBuf>,
+    
+    /// Print the length and MD5 hash of each binary packet received by the UNFLoader console.
+    #[bpaf(long)]
+    binary_summary: bool,
+    
+    /// ELF file of the running game. Addresses (e.g. 0x80012345) in text received by the UNFLoader console
+    /// or the run command are annotated with their function and source line.
+    #[bpaf(long, argument("FILE"))]
+    elf: Option<PathBuf>,
+    
+    /// Rewrites the CRC1/CRC2 boot checksum in the uploaded ROM's header, if it doesn't match the ROM's contents.
+    /// Otherwise a mismatch only produces a warning.
+    #[bpaf(long)]
+    fix_checksum: bool,
+    
+    /// After uploading, read the ROM back to verify it. Any mismatched parts are uploaded again.
+    #[bpaf(long)]
+    verify: bool,
+    
+    /// Enable extended addressing, even if the ROM is 64 MiB or smaller. It's enabled automatically for larger ROMs.
+    /// Note! 64drive HW1 does not support extended addressing.
+    #[bpaf(long)]
+    extended: bool,
+    
     /// On linux, the default ftdi_sio driver conflicts with D2XX. This command requires sudo, and will save
     /// a blacklist command to /etc/modprobe.d/ftdi_sio-blacklist.conf, to automatically disable ftdi_sio when a
     /// flashcart is plugged in. Otherwise you will be required to run 'sudo rmmod ftdi_sio' whenever connecting a flashcart.
-    #[cfg(target_os = "linux")]
+    #[cfg(all(target_os = "linux", feature = "d2xx"))]
     #[bpaf(long)]
     disable_sio: bool,
     
@@ -48,6 +99,120 @@ struct Args {
     ///   Options: error, warn, info, debug, trace
     #[bpaf(long, short)]
     verbose: Option<LevelFilter>,
+    
+    #[bpaf(external(command), optional)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Bpaf)]
+enum Command {
+    /// Back up or restore the cartridge's save data. The savetype is taken from --savetype (which may be
+    /// auto-detected from --upload).
+    #[bpaf(command)]
+    Save {
+        #[bpaf(external(save_action))]
+        action: SaveAction,
+    },
+    
+    /// Convert a ROM file to a different byte order. No flashcart is needed.
+    #[bpaf(command)]
+    Convert {
+        /// Byte order of the output file. Defaults to the output file's extension, or z64.
+        ///   Options: z64 (big-endian), v64 (byte-swapped), or n64 (little-endian)
+        #[bpaf(long, short)]
+        to: Option<ByteOrder>,
+        
+        #[bpaf(positional("INPUT"))]
+        input: PathBuf,
+        
+        #[bpaf(positional("OUTPUT"))]
+        output: PathBuf,
+    },
+    
+    /// Manage the flashcart's firmware.
+    #[cfg(feature = "firmware-update")]
+    #[bpaf(command)]
+    Firmware {
+        #[bpaf(external(firmware_action))]
+        action: FirmwareAction,
+    },
+    
+    /// Print the ROM's header, along with its detected CIC and savetype. No flashcart is needed.
+    #[bpaf(command)]
+    Info {
+        #[bpaf(positional("ROM"))]
+        rom: PathBuf,
+    },
+    
+    /// Tunnel a GDB remote debugging session to the cartridge, over UNFLoader RDB packets. The game needs
+    /// a GDB stub which understands them, such as the one in UNFLoader's debug library. Use with --upload to start the game first.
+    #[bpaf(command)]
+    Gdb {
+        /// Address to wait for GDB's connection on. Defaults to 127.0.0.1:2159.
+        #[bpaf(long, argument("ADDR"))]
+        listen: Option<String>,
+    },
+    
+    /// Upload and boot a ROM, then stream its UNFLoader text to stdout until a pattern or timeout is hit.
+    /// Exits with 0 if --until is received, 1 if a --fail-on pattern is received, 2 on timeout, or 3 if the ROM
+    /// couldn't be uploaded or the cartridge stopped responding.
+    /// --cic and --savetype are applied as usual.
+    #[bpaf(command)]
+    Run {
+        /// Text which ends the run successfully.
+        #[bpaf(long, argument("PATTERN"))]
+        until: Option<String>,
+        
+        /// Text which ends the run as a failure. May be given more than once.
+        #[bpaf(long, argument("PATTERN"))]
+        fail_on: Vec<String>,
+        
+        /// Maximum length of the run, e.g. 60s, 5m, or 500ms. By default there is no limit.
+        #[bpaf(long, argument("DURATION"))]
+        timeout: Option<Timeout>,
+        
+        /// Write the test results printed by the ROM to this file, as JUnit XML or JSON. Results are lines
+        /// in the format TEST:<suite>:<case>:<PASS|FAIL|SKIP>[:<milliseconds>[:<message>]]
+        #[bpaf(long, argument("FILE"))]
+        report: Option<PathBuf>,
+        
+        /// Format of the --report file. Defaults to json for .json files, and junit otherwise.
+        ///   Options: junit or json
+        #[bpaf(long, argument("FORMAT"))]
+        report_format: Option<ReportFormat>,
+        
+        #[bpaf(positional("ROM"))]
+        rom: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Bpaf)]
+enum SaveAction {
+    /// Download the save data to a file.
+    #[bpaf(command)]
+    Dump {
+        #[bpaf(positional("FILE"))]
+        file: PathBuf,
+    },
+    
+    /// Upload the save data from a file.
+    #[bpaf(command)]
+    Restore {
+        #[bpaf(positional("FILE"))]
+        file: PathBuf,
+    },
+}
+
+#[cfg(feature = "firmware-update")]
+#[derive(Debug, Clone, Bpaf)]
+enum FirmwareAction {
+    /// Upgrade the firmware from a vendor firmware file (.rpk for the 64drive). The file is checked
+    /// against the flashcart's model before anything is written.
+    #[bpaf(command)]
+    Update {
+        #[bpaf(positional("FILE"))]
+        file: PathBuf,
+    },
 }
 
 fn main() {
@@ -59,7 +224,7 @@ fn main() {
         logbuilder.init();
     }
     
-    #[cfg(target_os = "linux")]
+    #[cfg(all(target_os = "linux", feature = "d2xx"))]
     if args.disable_sio {
         match std::fs::write("/etc/modprobe.d/ftdi_sio-blacklist.conf", "# Generated by flashy64 to fix compatibility issue between FTDI 2DXX driver and ftdi_sio\nblacklist ftdi_sio") {
             Ok(()) => info!("File written. Any currently connected flashcarts should be unplugged, and reconnected."),
@@ -76,9 +241,10 @@ fn main() {
             if !carts.is_empty() {
                 info!("Available flashcarts:");
                 for mut cart in carts {
-                    let info = cart.info().unwrap();
-                    //TODO: Print version info using VERSION_REQUEST command function
-                    info!("{} : {}", info.serial_number, info.description);
+                    match cart.info() {
+                        Ok(info) => info!("{} : {} (firmware {})", info.serial_number, info.model, info.firmware.as_deref().unwrap_or("unknown")),
+                        Err(err) => error!("Err: {:?}", err)
+                    }
                 }
             } else {
                 info!("No flashcarts available. If you believe this is wrong, try running with the `-v debug` option, and see if any devices are in use (port_open == true).");
@@ -88,6 +254,28 @@ fn main() {
         return;
     }
     
+    if let Some(Command::Convert { to, ref input, ref output }) = args.command {
+        let to = to
+            .or_else(|| output.extension().and_then(|ext| ext.to_str()).and_then(|ext| ext.parse().ok()))
+            .unwrap_or(ByteOrder::BigEndian);
+        
+        let data = std::fs::read(input).unwrap();
+        match ByteOrder::detect(&data) {
+            Some(from) => match std::fs::write(output, rom::convert(&data, from, to)) {
+                Ok(()) => info!("Converted {} from {from} to {to}.", input.display()),
+                Err(err) => error!("Err: {}", err)
+            },
+            None => error!("Unable to detect the byte order of {}. Is it an N64 ROM?", input.display()),
+        }
+        
+        return;
+    }
+    
+    if let Some(Command::Info { ref rom }) = args.command {
+        print_rom_info(rom);
+        return;
+    }
+    
     
     let mut cart = match args.device {
         Some(serial) => flashy64_backend::from_serial(serial).expect("Failed to open device with serial: {serial}"),
@@ -103,18 +291,83 @@ fn main() {
         }
     };
     
-    if let Some(ref path) = args.upload {
-        let data = std::fs::read(path).unwrap();
+    #[cfg(feature = "firmware-update")]
+    if let Some(Command::Firmware { action: FirmwareAction::Update { ref file } }) = args.command {
+        update_firmware(cart.as_mut(), file);
+        return;
+    }
+    
+    // The run command uploads its own ROM, the same way as --upload.
+    let upload = match args.command {
+        Some(Command::Run { ref rom, .. }) => Some(rom.clone()),
+        _ => args.upload.clone(),
+    };
+    let running = matches!(args.command, Some(Command::Run { .. }));
+    
+    let mut rom = None;
+    let mut uploaded = false;
+    if let Some(ref path) = upload {
+        let mut data = match read_rom(path, args.ipl3.as_deref(), args.cic) {
+            Ok(data) => data,
+            Err(err) => {
+                error!("Unable to upload {}: {err}", path.display());
+                if running {
+                    std::process::exit(runner::Outcome::Error.exit_code());
+                }
+                return;
+            }
+        };
+        
+        let cic = match args.cic {
+            Some(Cic::Auto) | None => Cic::from_rom(&data),
+            Some(cic) => cic,
+        };
+        verify_checksum(&mut data, cic, args.fix_checksum);
+        
+        let mut bar = ProgressBar::new("Uploading");
+        uploaded = match cart.upload_rom(&data, Some(&mut |progress| bar.update(progress))) {
+            Ok(_) => {
+                info!("ROM Upload Complete.");
+                true
+            },
+            Err(err) => {
+                error!("Err: {:?}", err);
+                if running {
+                    std::process::exit(runner::Outcome::Error.exit_code());
+                }
+                false
+            }
+        };
+        
+        if uploaded && args.verify {
+            let mut bar = ProgressBar::new("Verifying");
+            match cart.verify_rom(&data, Some(&mut |progress| bar.update(progress))) {
+                Ok(offsets) if offsets.is_empty() => info!("ROM Verified."),
+                Ok(offsets) => {
+                    for offset in offsets {
+                        warn!("ROM mismatch at offset {offset:#010X}. Chunk was uploaded again.");
+                    }
+                    info!("ROM Verified.");
+                },
+                Err(err) => error!("Err: {:?}", err)
+            }
+        }
         
-        match cart.upload_rom(&data) {
-            Ok(_) => info!("ROM Upload Complete."),
+        rom = Some(data);
+    }
+    
+    // Uploads set extended addressing to suit the ROM's size, so this must come afterwards.
+    if args.extended {
+        match cart.set_extended(true) {
+            Ok(_) => info!("Extended Addressing Enabled."),
+            Err(Error::Unsupported) => error!("This flashcart doesn't support extended addressing."),
             Err(err) => error!("Err: {:?}", err)
         }
     }
     
     if let Some(mut cic) = args.cic {
         if cic == Cic::Auto {
-            let mut ipl3 = cart.download_rom(0x1000).unwrap_or_default();
+            let mut ipl3 = cart.download_rom(0x1000, None).unwrap_or_default();
             ipl3.resize(0x1000, 0x00);
             cic = Cic::from_ipl3(&ipl3[0x40..]);
         }
@@ -128,17 +381,18 @@ fn main() {
         }
     }
     
-    if let Some(mut savetype) = args.savetype {
-        if savetype == SaveType::Auto {
-            if let Some(ref path) = args.upload {
-                let data = std::fs::read(path).unwrap();
-                savetype = SaveType::from_rom(&data);
-            } else {
+    let savetype = args.savetype.map(|savetype| match savetype {
+        SaveType::Auto => match rom {
+            Some(ref data) => SaveType::from_rom(data),
+            None => {
                 warn!("Savetype autodection requires --upload to be used.");
-                savetype = SaveType::Unknown;
+                SaveType::Unknown
             }
-        }
-        
+        },
+        _ => savetype,
+    });
+    
+    if let Some(savetype) = savetype {
         match savetype {
             SaveType::Unknown => (),
             SaveType::Auto => error!("Unable to determine SaveType index."),
@@ -149,27 +403,195 @@ fn main() {
         }
     }
     
-    if args.unf {
-        loop {
-            use flashy64_backend::unfloader::DataType::*;
-            
-            match cart.recv_debug() {
-                Ok((kind, data)) => match kind {
-                    Text => print!("{}", std::str::from_utf8(&data).unwrap_or_default()),
-                    _ => println!("Unsupported data type: {kind:?}")
+    if let Some(Command::Save { ref action }) = args.command {
+        match savetype {
+            Some(savetype) if savetype.length().is_some() => match action {
+                SaveAction::Dump { file } => match cart.download_save(savetype, None) {
+                    Ok(data) => match std::fs::write(file, data) {
+                        Ok(()) => info!("Save data written to {}.", file.display()),
+                        Err(err) => error!("Err: {}", err)
+                    },
+                    Err(err) => error!("Err: {:?}", err)
                 },
-                Err(err) => match err {
-                    Error::FtdiTimeout(_) => continue,
-                    _ => panic!("{err:?}"),
-                }
+                SaveAction::Restore { file } => match std::fs::read(file).map(|data| cart.upload_save(savetype, &data, None)) {
+                    Ok(Ok(())) => info!("Save data restored from {}.", file.display()),
+                    Ok(Err(err)) => error!("Err: {:?}", err),
+                    Err(err) => error!("Err: {}", err)
+                },
+            },
+            Some(SaveType::Nothing) => error!("The savetype is 'none', so there is no save data."),
+            _ => error!("Unable to determine the savetype. Use --savetype to specify it (or --savetype auto with --upload)."),
+        }
+    }
+    
+    if uploaded {
+        if let Err(err) = cart.boot() {
+            error!("Err: {:?}", err);
+            if running {
+                std::process::exit(runner::Outcome::Error.exit_code());
             }
         }
     }
+    
+    if let Some(Command::Run { ref until, ref fail_on, timeout, ref report, report_format, .. }) = args.command {
+        let mut results = Report::new();
+        let symbols = args.elf.as_deref().and_then(load_symbols);
+        let outcome = runner::run(cart.as_mut(), until.as_deref(), fail_on, timeout.map(|timeout| timeout.0), &mut results, symbols.as_ref());
+        
+        if let Some(path) = report {
+            write_report(&results, path, report_format);
+        }
+        std::process::exit(outcome.exit_code());
+    }
+    
+    if let Some(Command::Gdb { ref listen }) = args.command {
+        gdb::run(cart.as_mut(), listen.as_deref().unwrap_or("127.0.0.1:2159"));
+        return;
+    }
+    
+    if args.unf {
+        let options = console::Options {
+            screenshot_dir: args.screenshot_dir.unwrap_or_else(|| PathBuf::from(".")),
+            binary_dir: args.binary_dir.unwrap_or_else(|| PathBuf::from(".")),
+            binary_summary: args.binary_summary,
+            symbols: args.elf.as_deref().and_then(load_symbols),
+            ipl3: args.ipl3,
+            cic: args.cic,
+        };
+        console::run(cart.as_mut(), upload.as_deref(), options);
+    }
 }
 
 
 
 
+/// Reads a ROM file, and converts it to big-endian byte order if needed. ELF files are converted to a
+/// ROM, behind the header and IPL3 from the `ipl3` file.
+fn read_rom(path: &Path, ipl3: Option<&Path>, cic: Option<Cic>) -> Result<Vec<u8>, String> {
+    let data = std::fs::read(path).map_err(|err| format!("{err}"))?;
+    if elf::is_elf(&data) {
+        let ipl3 = ipl3.ok_or("ELF files need an IPL3 to boot. Use --ipl3 to give a ROM or IPL3 dump to take it from.")?;
+        let boot = std::fs::read(ipl3).map_err(|err| format!("{}: {err}", ipl3.display()))?;
+        let name = path.file_stem().map(|name| name.to_string_lossy()).unwrap_or_default();
+        
+        let rom = elf::build_rom(&data, &boot, cic.unwrap_or(Cic::Auto), &name)?;
+        info!("Converted ELF to a {} byte ROM, using the IPL3 from {}.", rom.len(), ipl3.display());
+        return Ok(rom);
+    }
+    
+    match ByteOrder::detect(&data) {
+        Some(ByteOrder::BigEndian) => (),
+        Some(order) => info!("ROM is {order}, converting to big-endian."),
+        None => warn!("ROM header not recognized. Uploading it unchanged."),
+    }
+    
+    Ok(rom::normalize(&data).into_owned())
+}
+
+#[cfg(feature = "firmware-update")]
+fn update_firmware(cart: &mut dyn flashy64_backend::Flashcart, path: &Path) {
+    let firmware = match std::fs::read(path) {
+        Ok(firmware) => firmware,
+        Err(err) => {
+            error!("Unable to read {}: {err}", path.display());
+            return;
+        }
+    };
+    match cart.info() {
+        Ok(info) => info!("Current firmware: {} {}", info.model, info.firmware.as_deref().unwrap_or("unknown")),
+        Err(err) => error!("Err: {:?}", err)
+    }
+    
+    info!("Updating firmware from {}. Do not disconnect the flashcart until the update is complete.", path.display());
+    let mut bar = ProgressBar::new("Writing");
+    match cart.update_firmware(&firmware, Some(&mut |progress| bar.update(progress))) {
+        Ok(()) => info!("Firmware Update Complete. Power cycle the flashcart to use the new firmware."),
+        Err(Error::InvalidFirmware(err)) => error!("Invalid firmware file: {err}"),
+        Err(Error::Unsupported) => error!("This flashcart doesn't support firmware updates."),
+        Err(err) => error!("Err: {:?}", err)
+    }
+}
+
+/// Reads the symbols and line info of an ELF file, logging any error.
+fn load_symbols(path: &Path) -> Option<Symbolizer> {
+    let result = std::fs::read(path).map_err(|err| format!("{err}")).and_then(|data| Symbolizer::parse(&data));
+    match result {
+        Ok(symbols) => {
+            info!("Loaded {} symbols from {}.", symbols.symbols().len(), path.display());
+            Some(symbols)
+        },
+        Err(err) => {
+            error!("Unable to load symbols from {}: {err}", path.display());
+            None
+        }
+    }
+}
+
+fn write_report(report: &Report, path: &Path, format: Option<ReportFormat>) {
+    let format = format.unwrap_or(match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => ReportFormat::Json,
+        _ => ReportFormat::JUnit,
+    });
+    
+    info!("{} tests: {} passed, {} failed, {} skipped.", report.cases.len(),
+        report.count(TestStatus::Passed), report.count(TestStatus::Failed), report.count(TestStatus::Skipped));
+    match std::fs::write(path, report.write(format)) {
+        Ok(()) => info!("Test report written to {}.", path.display()),
+        Err(err) => error!("Err: {}", err)
+    }
+}
+
+/// Checks the ROM's boot checksum, and optionally fixes it. A bad checksum usually results in a black screen.
+fn verify_checksum(data: &mut [u8], cic: Cic, fix: bool) {
+    let (Ok(header), Some(expected)) = (RomHeader::parse(data), rom::checksum(data, cic)) else {
+        warn!("Unable to verify the ROM checksum, because the ROM header or CIC is unknown.");
+        return;
+    };
+    if (header.crc1, header.crc2) == expected {
+        debug!("ROM checksum is valid for CIC {cic}.");
+        return;
+    }
+    
+    if fix {
+        rom::write_checksum(data, expected);
+        info!("ROM checksum fixed for CIC {cic}: {:#010X} {:#010X}", expected.0, expected.1);
+    } else {
+        warn!("ROM checksum mismatch for CIC {cic}: {:#010X} {:#010X} vs expected {:#010X} {:#010X}. The ROM may not boot; use --fix-checksum to repair it.",
+            header.crc1, header.crc2, expected.0, expected.1);
+    }
+}
+
+fn print_rom_info(path: &Path) {
+    let data = std::fs::read(path).unwrap();
+    let header = match RomHeader::parse(&data) {
+        Ok(header) => header,
+        Err(err) => {
+            error!("Invalid ROM: {err}");
+            return;
+        }
+    };
+    let order = ByteOrder::detect(&data).unwrap_or(ByteOrder::BigEndian);
+    let unknown = "unknown";
+    
+    info!("Name:             {}", header.name);
+    info!("Game ID:          {} ({}, {})", header.game_id(), header.media_name().unwrap_or(unknown), header.region_name().unwrap_or(unknown));
+    info!("Revision:         {}", header.revision);
+    info!("CRC1/CRC2:        {:#010X} {:#010X}", header.crc1, header.crc2);
+    info!("Boot address:     {:#010X}", header.boot_address);
+    info!("Clock rate:       {:#010X}", header.clock_rate);
+    info!("Libultra version: {:#010X}", header.libultra_version);
+    info!("Byte order:       {order}");
+    info!("Size:             {} bytes", data.len());
+    let cic = Cic::from_rom(&data);
+    info!("CIC:              {cic}");
+    match rom::checksum(&data, cic) {
+        Some(expected) if (header.crc1, header.crc2) == expected => info!("Checksum:         valid"),
+        Some(expected) => warn!("Checksum:         mismatch, expected {:#010X} {:#010X}", expected.0, expected.1),
+        None => info!("Checksum:         unknown"),
+    }
+    info!("SaveType:         {:?}", SaveType::from_rom(&data));
+}
+
 fn logger_builder() -> Builder {
     let mut builder = Builder::new();
     
@@ -185,9 +607,13 @@ fn logger_builder() -> Builder {
             Error => style.set_color(Red).value("ERROR"),
         };
         
-        writeln!(f, "[{}] {}",
+        // The UNFLoader console puts the terminal in raw mode, where newlines don't return the cursor.
+        let newline = if console::ACTIVE.load(Ordering::Relaxed) { "\r\n" } else { "\n" };
+        
+        write!(f, "[{}] {}{}",
             level,
-            record.args()
+            record.args(),
+            newline
         )
     });
     
@@ -0,0 +1,62 @@
+use std::io::{stderr, Stderr, Write};
+use std::time::Duration;
+use crossterm::queue;
+use crossterm::style::Print;
+use crossterm::terminal::{Clear, ClearType};
+use flashy64_backend::progress::TransferProgress;
+
+const WIDTH: usize = 30;
+const MIB: f64 = 1024.0 * 1024.0;
+
+/// Progress bar for transfers, drawn on a single line of stderr along with the throughput and ETA.
+pub struct ProgressBar {
+    label: &'static str,
+    stderr: Stderr,
+}
+impl ProgressBar {
+    pub fn new(label: &'static str) -> Self {
+        Self {
+            label,
+            stderr: stderr(),
+        }
+    }
+    
+    /// Redraws the bar. The line is ended once the transfer is complete.
+    pub fn update(&mut self, progress: TransferProgress) {
+        let fraction = if progress.total == 0 { 1.0 } else { progress.bytes_done as f64 / progress.total as f64 };
+        let filled = (fraction * WIDTH as f64) as usize;
+        
+        let mut bar = "=".repeat(filled);
+        if filled < WIDTH {
+            bar.push('>');
+        }
+        
+        let eta = match progress.eta() {
+            Some(eta) if !progress.is_complete() => format!("ETA {}", format_duration(eta)),
+            _ => String::new(),
+        };
+        
+        let line = format!("\r{} [{bar:<WIDTH$}] {:>3.0}% {:.1}/{:.1} MiB {:.2} MiB/s {eta}",
+            self.label,
+            fraction * 100.0,
+            progress.bytes_done as f64 / MIB,
+            progress.total as f64 / MIB,
+            progress.rate / MIB,
+        );
+        
+        // Progress is cosmetic, so drawing errors are ignored.
+        let _ = queue!(self.stderr, Clear(ClearType::CurrentLine), Print(line));
+        if progress.is_complete() {
+            let _ = queue!(self.stderr, Print('\n'));
+        }
+        let _ = self.stderr.flush();
+    }
+}
+
+
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
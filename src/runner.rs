@@ -0,0 +1,149 @@
+use std::io::{stdout, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use log::{debug, error, info};
+use flashy64_backend::{Error, Flashcart};
+use flashy64_backend::elf::Symbolizer;
+use flashy64_backend::report::Report;
+use flashy64_backend::unfloader::DataType;
+
+/// Stop the run after this many consecutive failed polls, since the cartridge has likely stopped responding.
+const MAX_POLL_ERRORS: u32 = 10;
+
+/// Result of an on-hardware test run, reported through the process's exit code.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The `--until` pattern was received. Exit code 0.
+    Passed,
+    /// A `--fail-on` pattern was received. Exit code 1.
+    Failed,
+    /// Neither pattern was received before the timeout. Exit code 2.
+    TimedOut,
+    /// The ROM couldn't be read, uploaded, or started, or the cartridge stopped responding. Exit code 3.
+    Error,
+}
+impl Outcome {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Outcome::Passed => 0,
+            Outcome::Failed => 1,
+            Outcome::TimedOut => 2,
+            Outcome::Error => 3,
+        }
+    }
+}
+
+/// A duration given on the command line, such as "60s", "5m", "500ms", or "90" (seconds).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Timeout(pub Duration);
+impl FromStr for Timeout {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (value, unit) = s.split_at(split);
+        let value: u64 = value.parse().map_err(|_| "Expected a number followed by a unit, e.g. 60s".to_owned())?;
+        
+        Ok(Timeout(match unit {
+            "ms" => Duration::from_millis(value),
+            "" | "s" => Duration::from_secs(value),
+            "m" => Duration::from_secs(value * 60),
+            "h" => Duration::from_secs(value * 60 * 60),
+            
+            _ => return Err("Accepted units: ms, s, m, or h".into())
+        }))
+    }
+}
+
+/// Streams UNFLoader text from the running ROM to stdout, until one of the patterns is received or
+/// the timeout expires. The run also stops if the cartridge keeps failing to respond, or its transport
+/// fails (e.g. it was unplugged).
+/// 
+/// Patterns are matched within a line, so they may be split across several packets. Test result
+/// lines are collected into the report. Addresses in the printed text are annotated if symbols are given.
+pub fn run(cart: &mut dyn Flashcart, until: Option<&str>, fail_on: &[String], timeout: Option<Duration>, report: &mut Report, symbols: Option<&Symbolizer>) -> Outcome {
+    let outcome = watch(cart, until, fail_on, timeout, report, symbols);
+    // The final result line may not end with a newline.
+    report.finish();
+    
+    outcome
+}
+
+
+
+fn watch(cart: &mut dyn Flashcart, until: Option<&str>, fail_on: &[String], timeout: Option<Duration>, report: &mut Report, symbols: Option<&Symbolizer>) -> Outcome {
+    let start = Instant::now();
+    let mut stdout = stdout();
+    // Text received since the last newline.
+    let mut line = String::new();
+    let mut errors = 0;
+    
+    loop {
+        if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+            error!("Timed out after {:.1}s.", start.elapsed().as_secs_f64());
+            return Outcome::TimedOut;
+        }
+        
+        let packet = match cart.poll_debug() {
+            Ok(packet) => {
+                errors = 0;
+                packet
+            },
+            Err(err) => {
+                error!("Err: {err:?}");
+                errors += 1;
+                if matches!(err, Error::Transport(_)) || errors >= MAX_POLL_ERRORS {
+                    error!("Stopping the run, since the cartridge isn't responding.");
+                    return Outcome::Error;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+        };
+        let text = match packet {
+            Some((DataType::Text, data)) => String::from_utf8_lossy(&data).into_owned(),
+            Some((kind, _)) => {
+                debug!("Ignoring {kind:?} data.");
+                continue;
+            },
+            None => {
+                std::thread::sleep(Duration::from_millis(10));
+                continue;
+            },
+        };
+        
+        let annotated = symbols.map(|symbols| symbols.annotate(&text));
+        let _ = stdout.write_all(annotated.as_deref().unwrap_or(&text).as_bytes());
+        let _ = stdout.flush();
+        report.feed(&text);
+        
+        line.push_str(&text);
+        if let Some(outcome) = check_patterns(&line, until, fail_on) {
+            let _ = writeln!(stdout);
+            match outcome {
+                Outcome::Passed => info!("Test run passed after {:.1}s.", start.elapsed().as_secs_f64()),
+                _ => error!("Test run failed after {:.1}s.", start.elapsed().as_secs_f64()),
+            }
+            return outcome;
+        }
+        if let Some(newline) = line.rfind('\n') {
+            line.drain(..=newline);
+        }
+    }
+}
+
+
+
+/// Finds whichever pattern appears first in the text. A failure wins if both start at the same place.
+fn check_patterns(text: &str, until: Option<&str>, fail_on: &[String]) -> Option<Outcome> {
+    let failed = fail_on.iter().filter_map(|pattern| text.find(pattern.as_str())).min();
+    let passed = until.and_then(|pattern| text.find(pattern));
+    
+    match (passed, failed) {
+        (Some(passed), Some(failed)) if passed < failed => Some(Outcome::Passed),
+        (_, Some(_)) => Some(Outcome::Failed),
+        (Some(_), None) => Some(Outcome::Passed),
+        (None, None) => None,
+    }
+}